@@ -0,0 +1,250 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::expansion::LevelOptions;
+use crate::tiling::*;
+
+/// Position of a tile in the substitution hierarchy: the index of the seed tile it grew from and
+/// the child index chosen at every level below it. Child indices are packed into a single `u128`
+/// using the same number of bits for every level, see `TilingStep::address_bits`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TileAddress {
+    root: u32,
+    bits: u8,
+    depth: u8,
+    digits: u128,
+}
+
+impl TileAddress {
+    pub const MAX_BITS: u32 = u128::BITS;
+
+    pub fn root(root: usize, bits: u32) -> TileAddress {
+        TileAddress {
+            root: root as u32,
+            bits: bits as u8,
+            depth: 0,
+            digits: 0,
+        }
+    }
+
+    /// Index of the ancestor in the seed patch.
+    pub fn seed_index(&self) -> usize {
+        self.root as usize
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth as usize
+    }
+
+    /// Index into `TilingRule::result` of the ancestor at `level`, level 0 being the child of
+    /// the seed tile.
+    pub fn child(&self, level: usize) -> Option<usize> {
+        if level >= self.depth() {
+            return None;
+        }
+        let mask = (1u128 << self.bits) - 1;
+        Some(((self.digits >> (level as u32 * self.bits as u32)) & mask) as usize)
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.depth()).filter_map(|level| self.child(level))
+    }
+
+    /// Address of the ancestor `depth` levels below the seed, the address itself when it is not
+    /// that deep.
+    pub fn ancestor(&self, depth: usize) -> TileAddress {
+        if depth >= self.depth() {
+            return *self;
+        }
+        let used = depth as u32 * self.bits as u32;
+        TileAddress {
+            depth: depth as u8,
+            digits: self.digits & ((1u128 << used) - 1),
+            ..*self
+        }
+    }
+
+    /// Callers check with `TilingStep::address_bits` that the levels fit.
+    fn push(&self, child: usize) -> TileAddress {
+        let shift = self.depth as u32 * self.bits as u32;
+        TileAddress {
+            depth: self.depth + 1,
+            digits: self.digits | ((child as u128) << shift),
+            ..*self
+        }
+    }
+}
+
+impl Ord for TileAddress {
+    /// Expansion order: by seed index, then by child index from the top level down, ancestors
+    /// before their descendants.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.root
+            .cmp(&other.root)
+            .then_with(|| self.children().cmp(other.children()))
+            .then(self.bits.cmp(&other.bits))
+    }
+}
+
+impl PartialOrd for TileAddress {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for TileAddress {
+    /// Seed index followed by the child indices, e.g. `0:3.1.2`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.root)?;
+        for (i, child) in self.children().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            write!(f, "{child}")?;
+        }
+        Ok(())
+    }
+}
+
+impl TilingStep {
+    /// Bits needed to store one child index of any rule.
+    pub fn address_bits(&self) -> u32 {
+        let max_children = self.rules.iter().map(|rule| rule.result.len()).max();
+        let max_index = max_children.unwrap_or(0).saturating_sub(1);
+        (usize::BITS - max_index.leading_zeros()).max(1)
+    }
+
+    fn check_address_capacity(
+        &self,
+        input: &[TilePlacement],
+        levels: usize,
+    ) -> Result<u32, TilingError> {
+        let bits = self.address_bits();
+        if levels > (TileAddress::MAX_BITS / bits) as usize || input.len() > u32::MAX as usize {
+            return Err(TilingError::AddressTooLong { levels, bits });
+        }
+        return Ok(bits);
+    }
+
+    /// `expand_breadth_first` with the address of every tile, relative to `input`, as payload.
+    pub fn expand_with_addresses(
+        &self,
+        input: &[TilePlacement],
+        levels: usize,
+        options: &LevelOptions,
+        expand: impl Fn(&TilePlacement) -> bool + Sync,
+        on_level: impl FnMut(usize, &[(TilePlacement, TileAddress)]),
+    ) -> Result<(Vec<(TilePlacement, TileAddress)>, ExpansionResult), TilingError> {
+        let bits = self.check_address_capacity(input, levels)?;
+        let input = input
+            .iter()
+            .enumerate()
+            .map(|(i, tile)| (tile.clone(), TileAddress::root(i, bits)))
+            .collect();
+        return self.expand_breadth_first(
+            input,
+            levels,
+            options,
+            expand,
+            |address, child| address.push(child),
+            on_level,
+        );
+    }
+
+    /// Tile ids of the seed tile and of every ancestor down to the tile itself, which tells
+    /// which rule produced each level.
+    pub fn address_tile_ids(
+        &self,
+        input: &[TilePlacement],
+        address: &TileAddress,
+    ) -> Option<Vec<usize>> {
+        let mut tile_id = input.get(address.seed_index())?.tile_id;
+        let mut result = vec![tile_id];
+        for child in address.children() {
+            tile_id = self.rules.get(tile_id)?.result.get(child)?.tile_id;
+            result.push(tile_id);
+        }
+        return Some(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kurbo::Affine;
+
+    use super::*;
+    use crate::presets;
+    use crate::progress::Progress;
+
+    fn seed() -> Vec<TilePlacement> {
+        vec![
+            TilePlacement {
+                tile_id: 0,
+                transform: Affine::IDENTITY,
+            },
+            TilePlacement {
+                tile_id: 1,
+                transform: Affine::translate((3.0, 0.0)),
+            },
+        ]
+    }
+
+    fn expand(tiling: &TilingStep, levels: usize) -> Vec<(TilePlacement, TileAddress)> {
+        let progress = Progress::default();
+        let options = LevelOptions {
+            max_tiles: None,
+            parallel: false,
+            progress: &progress,
+        };
+        let expanded = tiling.expand_with_addresses(&seed(), levels, &options, |_| true, |_, _| {});
+        return expanded.unwrap().0;
+    }
+
+    /// Follows the child indices of `address` down from the seed.
+    fn placement(tiling: &TilingStep, address: &TileAddress) -> TilePlacement {
+        let mut placement = seed()[address.seed_index()].clone();
+        for child in address.children() {
+            let item = &tiling.rules[placement.tile_id].result[child];
+            placement = TilePlacement {
+                tile_id: item.tile_id,
+                transform: placement.transform * item.transform,
+            };
+        }
+        return placement;
+    }
+
+    #[test]
+    fn addresses_lead_back_to_their_tiles() {
+        let tiling = presets::load_preset("socolar_5").unwrap();
+        let levels = 3;
+        for (tile, address) in expand(&tiling, levels) {
+            assert_eq!(address.depth(), levels);
+            let found = placement(&tiling, &address);
+            assert_eq!(found.tile_id, tile.tile_id, "{address}");
+            assert_eq!(
+                found.transform.as_coeffs(),
+                tile.transform.as_coeffs(),
+                "{address}"
+            );
+            let ids = tiling.address_tile_ids(&seed(), &address).unwrap();
+            assert_eq!(ids.len(), levels + 1);
+            assert_eq!(ids[levels], tile.tile_id);
+            let parent = address.ancestor(levels - 1);
+            assert_eq!(parent.depth(), levels - 1);
+            assert_eq!(placement(&tiling, &parent).tile_id, ids[levels - 1]);
+        }
+    }
+
+    #[test]
+    fn order_matches_expansion_order() {
+        let tiling = presets::load_preset("socolar_5").unwrap();
+        let tiles = expand(&tiling, 3);
+        for pair in tiles.windows(2) {
+            assert!(pair[0].1 < pair[1].1, "{} before {}", pair[0].1, pair[1].1);
+        }
+        let (_, first) = &tiles[0];
+        let root = first.ancestor(0);
+        assert_eq!(root, TileAddress::root(0, tiling.address_bits()));
+        assert!(root < first.ancestor(1) && first.ancestor(1) < *first);
+    }
+}
@@ -0,0 +1,245 @@
+use std::sync::mpsc::{channel, Receiver, SendError, Sender, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::address::TileAddress;
+use crate::coloring::ColoringScheme;
+use crate::culling::Culler;
+use crate::layers::{LayerPath, LayerSplit};
+use crate::progress::Progress;
+use crate::render::*;
+use crate::tile_cache::{expand_cached_tiles, TileCache};
+use crate::tiling::*;
+
+/// Jobs finishing faster than this never send a preview, so quick updates don't flicker.
+const PREVIEW_DELAY: Duration = Duration::from_millis(200);
+
+enum Update {
+    Preview(Vec<LayerPath>),
+    Finished(Result<Rendered, TilingError>),
+}
+
+fn preview_layers(
+    tiling: &TilingStep,
+    settings: &RenderSettings,
+    tiles: &[(TilePlacement, TileAddress)],
+    culler: &Culler,
+    progress: &Progress,
+) -> Vec<LayerPath> {
+    let visible: Vec<TilePlacement> = tiles
+        .iter()
+        .map(|(tile, _)| tile)
+        .filter(|tile| culler.tile_visible(tiling, tile))
+        .cloned()
+        .collect();
+    // A preview only lives until the next level. It skips the slow plotter passes and whatever
+    // needs the hierarchy.
+    let coloring = match settings.coloring {
+        scheme if scheme.needs_addresses() => ColoringScheme::None,
+        scheme => scheme,
+    };
+    let settings = RenderSettings {
+        merge_edges: false,
+        optimize_travel: false,
+        layer_split: LayerSplit {
+            parent: false,
+            ..settings.layer_split
+        },
+        coloring,
+        ..settings.clone()
+    };
+    return tile_layers(tiling, &visible, None, &settings, progress).unwrap_or_default();
+}
+
+fn run_job(
+    tiling: &TilingStep,
+    settings: &RenderSettings,
+    cache: &mut TileCache,
+    progress: &Progress,
+    updates: &Sender<Update>,
+) {
+    if progress.is_cancelled() {
+        return;
+    }
+    if settings.depth_first
+        || settings.needs_hierarchy()
+        || !settings.substitutes()
+        || cache.can_reuse(tiling, settings)
+    {
+        let _ = updates.send(Update::Finished(cache.generate_path(tiling, settings, progress)));
+        return;
+    }
+    let start = Instant::now();
+    let culler = Culler::new(tiling, settings.bounds());
+    let expanded = expand_cached_tiles(tiling, settings, progress, |level, tiles| {
+        // The last level is the result itself.
+        if level > 0 && level < settings.levels && start.elapsed() >= PREVIEW_DELAY {
+            let layers = preview_layers(tiling, settings, tiles, &culler, progress);
+            let _ = updates.send(Update::Preview(layers));
+        }
+    });
+    let (tiles, addresses, expansion) = match expanded {
+        Ok(expanded) => expanded,
+        Err(err) => {
+            let _ = updates.send(Update::Finished(Err(err)));
+            return;
+        }
+    };
+    cache.insert_tiles(tiling, settings, tiles, addresses, expansion);
+    let _ = updates.send(Update::Finished(cache.generate_path(tiling, settings, progress)));
+}
+
+struct Request {
+    tiling: TilingStep,
+    settings: RenderSettings,
+    progress: Arc<Progress>,
+    updates: Sender<Update>,
+}
+
+/// Runs requests one at a time, skipping those that a newer request already replaced. The worker
+/// owns the tile cache, so jobs never wait for each other to release it.
+fn run_worker(requests: Receiver<Request>) {
+    let mut cache = TileCache::default();
+    while let Ok(mut request) = requests.recv() {
+        while let Ok(newer) = requests.try_recv() {
+            request = newer;
+        }
+        run_job(
+            &request.tiling,
+            &request.settings,
+            &mut cache,
+            &request.progress,
+            &request.updates,
+        );
+    }
+}
+
+struct Job {
+    fingerprint: u64,
+    output_fingerprint: u64,
+    settings: RenderSettings,
+    progress: Arc<Progress>,
+    updates: Receiver<Update>,
+    result: Option<Result<Rendered, TilingError>>,
+    lost: bool,
+}
+
+pub enum JobState<'a> {
+    Idle,
+    Running {
+        level: usize,
+        levels: usize,
+        tiles: usize,
+    },
+    Finished(&'a Result<Rendered, TilingError>),
+    /// The worker thread panicked.
+    Failed,
+}
+
+/// Generates tilings on a long-lived worker thread. Every new request cancels the running one,
+/// the last finished drawing stays visible until a preview or the new result replaces it.
+#[derive(Default)]
+pub struct BackgroundGenerator {
+    worker: Option<Sender<Request>>,
+    job: Option<Job>,
+    shown: Vec<LayerPath>,
+    shown_supertiles: Vec<SupertilePath>,
+}
+
+impl BackgroundGenerator {
+    /// Starts a new job unless the current one was started with the same input.
+    pub fn request(&mut self, tiling: &TilingStep, settings: &RenderSettings) {
+        let fingerprint = tiling.fingerprint();
+        let output_fingerprint = tiling.output_fingerprint();
+        if let Some(job) = &self.job {
+            if job.fingerprint == fingerprint
+                && job.output_fingerprint == output_fingerprint
+                && job.settings == *settings
+            {
+                return;
+            }
+            job.progress.cancel();
+        }
+        let progress = Arc::new(Progress::default());
+        let (sender, receiver) = channel();
+        self.job = Some(Job {
+            fingerprint,
+            output_fingerprint,
+            settings: settings.clone(),
+            progress: progress.clone(),
+            updates: receiver,
+            result: None,
+            lost: false,
+        });
+
+        let mut request = Request {
+            tiling: tiling.clone(),
+            settings: settings.clone(),
+            progress,
+            updates: sender,
+        };
+        if let Some(worker) = &self.worker {
+            match worker.send(request) {
+                Ok(()) => return,
+                // The worker panicked, the request goes to a new one.
+                Err(SendError(unsent)) => request = unsent,
+            }
+        }
+        let (worker, requests) = channel();
+        std::thread::spawn(move || run_worker(requests));
+        let _ = worker.send(request);
+        self.worker = Some(worker);
+    }
+
+    /// Takes in everything the worker sent since the last call.
+    pub fn poll(&mut self) {
+        let Some(job) = &mut self.job else {
+            return;
+        };
+        while job.result.is_none() && !job.lost {
+            match job.updates.try_recv() {
+                Ok(Update::Preview(layers)) => {
+                    self.shown = layers;
+                    self.shown_supertiles.clear();
+                }
+                Ok(Update::Finished(result)) => {
+                    (self.shown, self.shown_supertiles) = match &result {
+                        Ok(rendered) => (rendered.layers.clone(), rendered.supertiles.clone()),
+                        Err(_) => (Vec::new(), Vec::new()),
+                    };
+                    job.result = Some(result);
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    // The worker only drops the current job without a result if it panicked.
+                    job.lost = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    pub fn layers(&self) -> &[LayerPath] {
+        &self.shown
+    }
+
+    pub fn supertiles(&self) -> &[SupertilePath] {
+        &self.shown_supertiles
+    }
+
+    pub fn state(&self) -> JobState<'_> {
+        match &self.job {
+            None => JobState::Idle,
+            Some(Job { lost: true, .. }) => JobState::Failed,
+            Some(Job {
+                result: Some(result),
+                ..
+            }) => JobState::Finished(result),
+            Some(job) => JobState::Running {
+                level: job.progress.level(),
+                levels: job.settings.levels,
+                tiles: job.progress.tiles(),
+            },
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use std::fmt;
+
+use kurbo::{BezPath, Circle, Line, ParamCurve, PathEl, PathSeg, Point, Rect, Shape};
+
+use crate::tiling_validation::polygon_contains;
+
+/// Tolerance used to flatten curved masks into polygons, in mm.
+const MASK_FLATTEN_TOLERANCE: f64 = 0.01;
+/// Crossings this close to a segment end are ignored, so tiles touching the mask from the inside
+/// are not cut into pieces.
+const CROSSING_EPSILON: f64 = 1e-9;
+/// Upper bound on the number of horizontal strips the mask edges are bucketed into.
+const MAX_STRIPS: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClipMode {
+    /// Cut segments at the mask border.
+    Trim,
+    /// Drop every tile that is not fully inside the mask, nothing gets cut.
+    WholeTiles,
+}
+
+#[derive(Debug)]
+pub struct MaskError(kurbo::SvgParseError);
+
+impl fmt::Display for MaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid mask path: {}", self.0)
+    }
+}
+
+impl std::error::Error for MaskError {}
+
+/// A closed shape in page coordinates. Paths with several subpaths use the even-odd rule, so
+/// masks can have holes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClipMask {
+    rings: Vec<Vec<Point>>,
+    bounds: Rect,
+    edges: Vec<Line>,
+    /// Indices into `edges` for each horizontal strip of `bounds` the edge passes through.
+    strips: Vec<Vec<usize>>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ClipStats {
+    pub input_tiles: usize,
+    pub output_tiles: usize,
+}
+
+impl ClipMask {
+    pub fn from_path(path: &BezPath) -> ClipMask {
+        let mut rings: Vec<Vec<Point>> = Vec::new();
+        path.flatten(MASK_FLATTEN_TOLERANCE, |el| match el {
+            PathEl::MoveTo(p) => rings.push(vec![p]),
+            PathEl::LineTo(p) => match rings.last_mut() {
+                Some(ring) => ring.push(p),
+                None => rings.push(vec![p]),
+            },
+            _ => {}
+        });
+        rings.retain(|ring| ring.len() >= 3);
+        let bounds = rings
+            .iter()
+            .flatten()
+            .fold(None, |bounds: Option<Rect>, p| {
+                Some(bounds.map_or(Rect::from_points(*p, *p), |b| b.union_pt(*p)))
+            })
+            .unwrap_or(Rect::ZERO);
+        let edges: Vec<Line> = rings
+            .iter()
+            .flat_map(|ring| {
+                (0..ring.len()).map(move |i| Line::new(ring[i], ring[(i + 1) % ring.len()]))
+            })
+            .collect();
+        let strip_count = edges.len().clamp(1, MAX_STRIPS);
+        let mut mask = ClipMask {
+            rings,
+            bounds,
+            edges,
+            strips: vec![Vec::new(); strip_count],
+        };
+        for (i, edge) in mask.edges.iter().enumerate() {
+            let edge_bounds = edge.bounding_box();
+            for strip in mask.strip_range(edge_bounds.y0, edge_bounds.y1) {
+                mask.strips[strip].push(i);
+            }
+        }
+        return mask;
+    }
+
+    pub fn rect(rect: Rect) -> ClipMask {
+        ClipMask::from_path(&rect.to_path(MASK_FLATTEN_TOLERANCE))
+    }
+
+    pub fn circle(center: Point, radius: f64) -> ClipMask {
+        ClipMask::from_path(&Circle::new(center, radius).to_path(MASK_FLATTEN_TOLERANCE))
+    }
+
+    /// Parses SVG path data, e.g. copied from the `d` attribute of a drawing.
+    pub fn from_svg(data: &str) -> Result<ClipMask, MaskError> {
+        let path = BezPath::from_svg(data).map_err(MaskError)?;
+        return Ok(ClipMask::from_path(&path));
+    }
+
+    pub fn to_path(&self) -> BezPath {
+        let mut path = BezPath::new();
+        for ring in &self.rings {
+            path.move_to(ring[0]);
+            for p in &ring[1..] {
+                path.line_to(*p);
+            }
+            path.close_path();
+        }
+        return path;
+    }
+
+    pub fn contains(&self, p: Point) -> bool {
+        if !self.bounds.contains(p) {
+            return false;
+        }
+        self.rings
+            .iter()
+            .filter(|ring| polygon_contains(ring, p))
+            .count()
+            % 2
+            == 1
+    }
+
+    /// Strips overlapping the vertical range `y0..=y1`, clamped to the mask bounds.
+    fn strip_range(&self, y0: f64, y1: f64) -> std::ops::RangeInclusive<usize> {
+        let count = self.strips.len();
+        let height = self.bounds.height();
+        let strip = |y: f64| {
+            if height <= 0.0 {
+                return 0;
+            }
+            let t = ((y - self.bounds.y0) / height * count as f64).floor();
+            return (t.max(0.0) as usize).min(count - 1);
+        };
+        return strip(y0)..=strip(y1);
+    }
+
+    /// Mask edges that may overlap `rect`, each listed once.
+    fn candidate_edges(&self, rect: Rect) -> Vec<usize> {
+        let mut result: Vec<usize> = self
+            .strip_range(rect.y0, rect.y1)
+            .flat_map(|strip| self.strips[strip].iter().copied())
+            .collect();
+        result.sort_unstable();
+        result.dedup();
+        return result;
+    }
+
+    /// Sorted parameters along `seg` where it crosses the mask border, including both ends.
+    fn split_points(&self, seg: &PathSeg) -> Vec<f64> {
+        let mut result = vec![0.0, 1.0];
+        let seg_bounds = seg.bounding_box();
+        if overlaps(seg_bounds, self.bounds) {
+            for i in self.candidate_edges(seg_bounds) {
+                let edge = self.edges[i];
+                if !overlaps(seg_bounds, edge.bounding_box()) {
+                    continue;
+                }
+                for hit in seg.intersect_line(edge) {
+                    if hit.segment_t > CROSSING_EPSILON && hit.segment_t < 1.0 - CROSSING_EPSILON {
+                        result.push(hit.segment_t);
+                    }
+                }
+            }
+        }
+        result.sort_by(f64::total_cmp);
+        result.dedup();
+        return result;
+    }
+
+    fn fully_inside(&self, subpath: &BezPath) -> bool {
+        subpath.segments().all(|seg| {
+            self.split_points(&seg).len() == 2 && self.contains(seg.eval(0.5))
+        })
+    }
+
+    /// Clips every closed subpath of `path`, which is expected to hold one tile per subpath.
+    pub fn clip_path(&self, path: &BezPath, mode: ClipMode) -> (BezPath, ClipStats) {
+        let mut result = BezPath::new();
+        let mut stats = ClipStats::default();
+        for subpath in subpaths(path) {
+            stats.input_tiles += 1;
+            if self.fully_inside(&subpath) {
+                result.extend(subpath.elements().iter().copied());
+                stats.output_tiles += 1;
+                continue;
+            }
+            if mode == ClipMode::WholeTiles {
+                continue;
+            }
+            let mut kept_any = false;
+            let mut current: Option<Point> = None;
+            for seg in subpath.segments() {
+                let splits = self.split_points(&seg);
+                for range in splits.windows(2) {
+                    if !self.contains(seg.eval((range[0] + range[1]) * 0.5)) {
+                        continue;
+                    }
+                    let piece = seg.subsegment(range[0]..range[1]);
+                    if current != Some(piece.start()) {
+                        result.move_to(piece.start());
+                    }
+                    append_segment(&mut result, &piece);
+                    current = Some(piece.end());
+                    kept_any = true;
+                }
+            }
+            if kept_any {
+                stats.output_tiles += 1;
+            }
+        }
+        return (result, stats);
+    }
+}
+
+/// Like `Rect::intersect` but also true for rectangles of zero width or height, which is what
+/// the bounding box of a horizontal or vertical line is.
+fn overlaps(a: Rect, b: Rect) -> bool {
+    a.x0 <= b.x1 && b.x0 <= a.x1 && a.y0 <= b.y1 && b.y0 <= a.y1
+}
+
+fn subpaths(path: &BezPath) -> Vec<BezPath> {
+    let mut result: Vec<BezPath> = Vec::new();
+    for el in path.elements() {
+        if let PathEl::MoveTo(_) = el {
+            result.push(BezPath::new());
+        }
+        if let Some(current) = result.last_mut() {
+            current.push(*el);
+        }
+    }
+    return result;
+}
+
+fn append_segment(path: &mut BezPath, seg: &PathSeg) {
+    match seg {
+        PathSeg::Line(line) => path.line_to(line.p1),
+        PathSeg::Quad(quad) => path.quad_to(quad.p1, quad.p2),
+        PathSeg::Cubic(cubic) => path.curve_to(cubic.p1, cubic.p2, cubic.p3),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(x: f64, y: f64, side: f64) -> BezPath {
+        return Rect::new(x, y, x + side, y + side).to_path(MASK_FLATTEN_TOLERANCE);
+    }
+
+    fn lines(path: &BezPath) -> Vec<(Point, Point)> {
+        return path
+            .segments()
+            .map(|seg| (seg.start(), seg.end()))
+            .filter(|(a, b)| a.distance(*b) > 1e-9)
+            .collect();
+    }
+
+    fn same_line(actual: (Point, Point), expected: ((f64, f64), (f64, f64))) -> bool {
+        let (a, b) = (Point::from(expected.0), Point::from(expected.1));
+        return actual.0.distance(a) < 1e-9 && actual.1.distance(b) < 1e-9;
+    }
+
+    #[test]
+    fn trims_segments_crossing_the_mask() {
+        let mask = ClipMask::rect(Rect::new(0.0, 0.0, 10.0, 10.0));
+        let mut path = BezPath::new();
+        path.move_to((-5.0, 5.0));
+        path.line_to((15.0, 5.0));
+        let (clipped, stats) = mask.clip_path(&path, ClipMode::Trim);
+        let pieces = lines(&clipped);
+        assert_eq!(pieces.len(), 1);
+        assert!(same_line(pieces[0], ((0.0, 5.0), (10.0, 5.0))));
+        assert_eq!(stats.output_tiles, 1);
+    }
+
+    #[test]
+    fn holes_follow_the_even_odd_rule() {
+        let mask = ClipMask::from_svg("M0 0 H10 V10 H0 Z M3 3 H7 V7 H3 Z").unwrap();
+        assert!(mask.contains(Point::new(1.0, 5.0)));
+        assert!(!mask.contains(Point::new(5.0, 5.0)));
+        assert!(!mask.contains(Point::new(11.0, 5.0)));
+
+        let mut path = BezPath::new();
+        path.move_to((-1.0, 5.0));
+        path.line_to((11.0, 5.0));
+        let (clipped, _) = mask.clip_path(&path, ClipMode::Trim);
+        let pieces = lines(&clipped);
+        assert_eq!(pieces.len(), 2);
+        assert!(same_line(pieces[0], ((0.0, 5.0), (3.0, 5.0))));
+        assert!(same_line(pieces[1], ((7.0, 5.0), (10.0, 5.0))));
+
+        // A tile sitting in the hole is dropped, one around it is cut open.
+        let (clipped, stats) = mask.clip_path(&square(4.0, 4.0, 2.0), ClipMode::Trim);
+        assert!(lines(&clipped).is_empty());
+        assert_eq!(stats.output_tiles, 0);
+        let (_, stats) = mask.clip_path(&square(2.0, 2.0, 6.0), ClipMode::WholeTiles);
+        assert_eq!(stats.output_tiles, 1);
+    }
+
+    #[test]
+    fn whole_tiles_drops_partly_outside_tiles() {
+        let mask = ClipMask::rect(Rect::new(0.0, 0.0, 10.0, 10.0));
+        let inside = square(1.0, 1.0, 2.0);
+        let mut path = inside.clone();
+        path.extend(square(8.0, 8.0, 4.0));
+
+        let (clipped, stats) = mask.clip_path(&path, ClipMode::WholeTiles);
+        assert_eq!(clipped, inside);
+        assert_eq!((stats.input_tiles, stats.output_tiles), (2, 1));
+
+        let (clipped, stats) = mask.clip_path(&path, ClipMode::Trim);
+        assert_eq!((stats.input_tiles, stats.output_tiles), (2, 2));
+        let inside_mask = |(a, b): &(Point, Point)| mask.bounds.contains(a.midpoint(*b));
+        assert!(lines(&clipped).iter().all(inside_mask));
+    }
+
+    #[test]
+    fn crossings_are_found_on_large_masks() {
+        let mask = ClipMask::circle(Point::new(0.0, 0.0), 5.0);
+        assert!(mask.strips.len() > 1);
+        for i in 0..20 {
+            let y = 0.437 * i as f64 - 4.1;
+            let seg = PathSeg::Line(Line::new((-6.0, y), (6.0, y)));
+            let inner: Vec<f64> = mask.split_points(&seg)[1..].to_vec();
+            assert_eq!(inner.len(), 3, "row {y}");
+            for t in &inner[..2] {
+                let distance = seg.eval(*t).to_vec2().hypot();
+                assert!((distance - 5.0).abs() < 0.02, "row {y}: {distance}");
+            }
+        }
+    }
+}
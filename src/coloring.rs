@@ -0,0 +1,312 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+
+use kurbo::{Line, Point};
+
+use crate::address::TileAddress;
+use crate::edges::{collinear_groups, LineInterval};
+use crate::layers::orientation_class;
+use crate::progress::Progress;
+use crate::tiling::*;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColoringScheme {
+    #[default]
+    None,
+    /// Neighbouring tiles get different colours, using as few as the DSatur heuristic finds.
+    Proper,
+    Prototile,
+    Orientation,
+    /// Tiles with the same ancestor `levels_up` levels above them share a colour, neighbouring
+    /// ancestors get different ones.
+    Ancestor {
+        levels_up: usize,
+    },
+}
+
+impl ColoringScheme {
+    pub fn needs_addresses(&self) -> bool {
+        matches!(self, ColoringScheme::Ancestor { .. })
+    }
+}
+
+pub struct TileColoring {
+    /// Colour index of every tile.
+    pub colors: Vec<usize>,
+    pub count: usize,
+}
+
+/// Tiles that share a piece of an edge, tiles only touching at a corner are not neighbours.
+pub struct TileGraph {
+    pub neighbors: Vec<Vec<usize>>,
+}
+
+/// Piece of an edge where two tiles meet.
+pub struct SharedEdge {
+    /// Tile index and edge index, edge i running from corner i to corner i + 1.
+    pub a: (usize, usize),
+    pub b: (usize, usize),
+    /// Both tiles run along the edge in the same direction.
+    pub same_direction: bool,
+    /// Middle of the shared piece.
+    pub location: Point,
+}
+
+impl TileGraph {
+    pub fn new(tiling: &TilingStep, tiles: &[TilePlacement], tolerance: f64) -> TileGraph {
+        let mut neighbors = vec![Vec::new(); tiles.len()];
+        for shared in shared_edges(tiling, tiles, tolerance) {
+            neighbors[shared.a.0].push(shared.b.0);
+            neighbors[shared.b.0].push(shared.a.0);
+        }
+        for list in &mut neighbors {
+            list.sort_unstable();
+            list.dedup();
+        }
+        return TileGraph { neighbors };
+    }
+
+    /// Graph with one node per group, two groups are adjacent when any of their tiles are.
+    pub fn contract(&self, groups: &[usize], group_count: usize) -> TileGraph {
+        let mut neighbors = vec![Vec::new(); group_count];
+        for (i, list) in self.neighbors.iter().enumerate() {
+            for j in list {
+                if groups[i] != groups[*j] {
+                    neighbors[groups[i]].push(groups[*j]);
+                }
+            }
+        }
+        for list in &mut neighbors {
+            list.sort_unstable();
+            list.dedup();
+        }
+        return TileGraph { neighbors };
+    }
+
+    /// DSatur: repeatedly colours the node with the most differently coloured neighbours, ties
+    /// broken by degree and then index, with the smallest colour its neighbours don't use.
+    pub fn dsatur(&self) -> TileColoring {
+        let n = self.neighbors.len();
+        let mut colors = vec![usize::MAX; n];
+        let mut neighbor_colors: Vec<Vec<usize>> = vec![Vec::new(); n];
+        let mut queue: BinaryHeap<(usize, usize, Reverse<usize>)> = (0..n)
+            .map(|i| (0, self.neighbors[i].len(), Reverse(i)))
+            .collect();
+        let mut count = 0;
+        while let Some((saturation, _, Reverse(i))) = queue.pop() {
+            if colors[i] != usize::MAX || saturation != neighbor_colors[i].len() {
+                continue;
+            }
+            let color = (0..).find(|c| !neighbor_colors[i].contains(c)).unwrap();
+            colors[i] = color;
+            count = count.max(color + 1);
+            for j in &self.neighbors[i] {
+                if colors[*j] == usize::MAX && !neighbor_colors[*j].contains(&color) {
+                    neighbor_colors[*j].push(color);
+                    queue.push((
+                        neighbor_colors[*j].len(),
+                        self.neighbors[*j].len(),
+                        Reverse(*j),
+                    ));
+                }
+            }
+        }
+        return TileColoring { colors, count };
+    }
+}
+
+/// Every pair of tiles with edges that are collinear within `tolerance` and overlap by more than
+/// `tolerance`, so edges meeting T-junctions are found too. Curved edges count as their chord.
+pub fn shared_edges(
+    tiling: &TilingStep,
+    tiles: &[TilePlacement],
+    tolerance: f64,
+) -> Vec<SharedEdge> {
+    let mut lines = Vec::new();
+    // Tile and edge index of every line.
+    let mut owners = Vec::new();
+    for (i, tile) in tiles.iter().enumerate() {
+        let Some(rule) = tiling.rules.get(tile.tile_id) else {
+            continue;
+        };
+        let corners = &rule.tile.corners;
+        let n = corners.len();
+        for k in 0..n {
+            lines.push(Line::new(
+                tile.transform * corners[k],
+                tile.transform * corners[(k + 1) % n],
+            ));
+            owners.push((i, k));
+        }
+    }
+
+    let mut result = Vec::new();
+    for group in collinear_groups(&lines, tolerance) {
+        let mut active: Vec<&LineInterval> = Vec::new();
+        for interval in &group.intervals {
+            active.retain(|other| other.end > interval.start + tolerance);
+            for other in &active {
+                let (a, b) = (owners[other.index], owners[interval.index]);
+                if a.0 == b.0 {
+                    continue;
+                }
+                let (line_a, line_b) = (lines[other.index], lines[interval.index]);
+                let middle = 0.5 * (interval.start + other.end.min(interval.end));
+                result.push(SharedEdge {
+                    a,
+                    b,
+                    same_direction: (line_a.p1 - line_a.p0).dot(line_b.p1 - line_b.p0) > 0.0,
+                    location: (group.dir * middle + group.normal * interval.offset).to_point(),
+                });
+            }
+            active.push(interval);
+        }
+    }
+    return result;
+}
+
+/// Numbers the distinct keys in sorted order.
+fn index_keys<K: Ord + Clone>(keys: &[K]) -> (Vec<usize>, usize) {
+    let mut indices = BTreeMap::new();
+    for key in keys {
+        indices.insert(key.clone(), 0);
+    }
+    for (i, index) in indices.values_mut().enumerate() {
+        *index = i;
+    }
+    let colors = keys.iter().map(|key| indices[key]).collect();
+    (colors, indices.len())
+}
+
+/// Colours the tiles of an expansion. `addresses` has to come from the same expansion, see
+/// `TilingStep::expand_with_addresses`, colouring by ancestor fails without them. Returns `None`
+/// for `ColoringScheme::None`.
+pub fn color_tiles(
+    tiling: &TilingStep,
+    tiles: &[TilePlacement],
+    addresses: Option<&[TileAddress]>,
+    scheme: ColoringScheme,
+    tolerance: f64,
+    progress: &Progress,
+) -> Result<Option<TileColoring>, TilingError> {
+    progress.check()?;
+    let coloring = match scheme {
+        ColoringScheme::None => None,
+        ColoringScheme::Proper => {
+            let graph = TileGraph::new(tiling, tiles, tolerance);
+            progress.check()?;
+            Some(graph.dsatur())
+        }
+        ColoringScheme::Prototile => {
+            let ids: Vec<usize> = tiles.iter().map(|tile| tile.tile_id).collect();
+            let (colors, count) = index_keys(&ids);
+            Some(TileColoring { colors, count })
+        }
+        ColoringScheme::Orientation => {
+            let classes: Vec<(bool, i64)> = tiles
+                .iter()
+                .map(|tile| orientation_class(&tile.transform))
+                .collect();
+            let (colors, count) = index_keys(&classes);
+            Some(TileColoring { colors, count })
+        }
+        ColoringScheme::Ancestor { levels_up } => {
+            let Some(addresses) = addresses.filter(|addresses| addresses.len() == tiles.len())
+            else {
+                return Err(TilingError::MissingAddresses);
+            };
+            let mut group_ids: HashMap<TileAddress, usize> = HashMap::new();
+            let groups: Vec<usize> = addresses
+                .iter()
+                .map(|address| {
+                    let ancestor = address.ancestor(address.depth().saturating_sub(levels_up));
+                    let next = group_ids.len();
+                    *group_ids.entry(ancestor).or_insert(next)
+                })
+                .collect();
+            let graph = TileGraph::new(tiling, tiles, tolerance).contract(&groups, group_ids.len());
+            progress.check()?;
+            let group_colors = graph.dsatur();
+            Some(TileColoring {
+                colors: groups
+                    .iter()
+                    .map(|group| group_colors.colors[*group])
+                    .collect(),
+                count: group_colors.count,
+            })
+        }
+    };
+    return Ok(coloring);
+}
+
+#[cfg(test)]
+mod tests {
+    use kurbo::Affine;
+
+    use super::*;
+    use crate::expansion::LevelOptions;
+    use crate::presets;
+
+    const TOLERANCE: f64 = 1e-6;
+
+    fn patch(tiling: &TilingStep) -> (Vec<TilePlacement>, Vec<TileAddress>) {
+        let seed = [TilePlacement {
+            tile_id: 1,
+            transform: Affine::scale(10.0),
+        }];
+        let progress = Progress::default();
+        let options = LevelOptions {
+            max_tiles: None,
+            parallel: false,
+            progress: &progress,
+        };
+        let (tiles, _) = tiling
+            .expand_with_addresses(&seed, 4, &options, |_| true, |_, _| {})
+            .unwrap();
+        return tiles.into_iter().unzip();
+    }
+
+    #[test]
+    fn proper_coloring_separates_neighbours() {
+        let tiling = presets::load_preset("socolar_5").unwrap();
+        let (tiles, _) = patch(&tiling);
+        let progress = Progress::default();
+        let scheme = ColoringScheme::Proper;
+        let coloring = color_tiles(&tiling, &tiles, None, scheme, TOLERANCE, &progress);
+        let coloring = coloring.unwrap().unwrap();
+        assert_eq!(coloring.colors.len(), tiles.len());
+        assert!(coloring.count >= 2);
+        let graph = TileGraph::new(&tiling, &tiles, TOLERANCE);
+        assert!(graph.neighbors.iter().flatten().next().is_some());
+        for (i, neighbors) in graph.neighbors.iter().enumerate() {
+            assert!(coloring.colors[i] < coloring.count);
+            for &j in neighbors {
+                assert_ne!(coloring.colors[i], coloring.colors[j], "tiles {i} and {j}");
+            }
+        }
+    }
+
+    #[test]
+    fn ancestor_coloring_needs_addresses() {
+        let tiling = presets::load_preset("socolar_5").unwrap();
+        let (tiles, addresses) = patch(&tiling);
+        let progress = Progress::default();
+        let scheme = ColoringScheme::Ancestor { levels_up: 1 };
+        let coloring = color_tiles(&tiling, &tiles, None, scheme, TOLERANCE, &progress);
+        assert!(matches!(coloring, Err(TilingError::MissingAddresses)));
+        let partial = Some(&addresses[1..]);
+        let coloring = color_tiles(&tiling, &tiles, partial, scheme, TOLERANCE, &progress);
+        assert!(matches!(coloring, Err(TilingError::MissingAddresses)));
+
+        let all = Some(addresses.as_slice());
+        let coloring = color_tiles(&tiling, &tiles, all, scheme, TOLERANCE, &progress);
+        let coloring = coloring.unwrap().unwrap();
+        for (i, a) in addresses.iter().enumerate() {
+            for (j, b) in addresses.iter().enumerate() {
+                if a.ancestor(a.depth() - 1) == b.ancestor(b.depth() - 1) {
+                    assert_eq!(coloring.colors[i], coloring.colors[j]);
+                }
+            }
+        }
+    }
+}
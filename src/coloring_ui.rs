@@ -0,0 +1,56 @@
+use whiskers::widgets::Widget;
+use whiskers::{prelude::*, register_widget_ui};
+
+use crate::coloring::ColoringScheme;
+
+#[derive(Default)]
+pub struct ColoringSchemeWidget {}
+
+fn scheme_name(scheme: &ColoringScheme) -> &'static str {
+    match scheme {
+        ColoringScheme::None => "None",
+        ColoringScheme::Proper => "Neighbours differ",
+        ColoringScheme::Prototile => "Prototile",
+        ColoringScheme::Orientation => "Orientation",
+        ColoringScheme::Ancestor { .. } => "Ancestor",
+    }
+}
+
+impl Widget<ColoringScheme> for ColoringSchemeWidget {
+    fn ui(&self, ui: &mut egui::Ui, label: &str, value: &mut ColoringScheme) -> bool {
+        let mut changed = false;
+        ui.label(label);
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source(ui.id().with("coloring_scheme"))
+                .selected_text(scheme_name(value))
+                .show_ui(ui, |ui| {
+                    let ancestor = match value {
+                        ColoringScheme::Ancestor { levels_up } => *levels_up,
+                        _ => 1,
+                    };
+                    for scheme in [
+                        ColoringScheme::None,
+                        ColoringScheme::Proper,
+                        ColoringScheme::Prototile,
+                        ColoringScheme::Orientation,
+                        ColoringScheme::Ancestor {
+                            levels_up: ancestor,
+                        },
+                    ] {
+                        changed |= ui
+                            .selectable_value(value, scheme, scheme_name(&scheme))
+                            .changed();
+                    }
+                });
+            if let ColoringScheme::Ancestor { levels_up } = value {
+                ui.label("levels up");
+                changed |= ui
+                    .add(egui::DragValue::new(levels_up).clamp_range(1..=30))
+                    .changed();
+            }
+        });
+        changed
+    }
+}
+
+register_widget_ui!(ColoringScheme, ColoringSchemeWidget);
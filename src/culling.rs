@@ -0,0 +1,318 @@
+use kurbo::{Point, Rect, Vec2};
+
+use crate::tiling::*;
+
+const HULL_ITERATIONS: usize = 200;
+const HULL_EPSILON: f64 = 1e-10;
+/// Relative growth applied to converged hulls to cover what the iteration didn't reach.
+const HULL_MARGIN: f64 = 1e-6;
+
+fn cross(o: Point, a: Point, b: Point) -> f64 {
+    (a - o).cross(b - o)
+}
+
+/// Andrew's monotone chain, counter-clockwise without collinear points.
+pub fn convex_hull(points: &[Point]) -> Vec<Point> {
+    let mut points: Vec<Point> = points.to_vec();
+    points.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+    let mut hull: Vec<Point> = Vec::with_capacity(points.len() + 1);
+    let add = |hull: &mut Vec<Point>, p: Point, start: usize| {
+        while hull.len() >= start + 2 && cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0.0 {
+            hull.pop();
+        }
+        hull.push(p);
+    };
+    for p in &points {
+        add(&mut hull, *p, 0);
+    }
+    let lower = hull.len();
+    for p in points.iter().rev().skip(1) {
+        add(&mut hull, *p, lower - 1);
+    }
+    hull.pop();
+    return hull;
+}
+
+fn project(points: &[Point], axis: Vec2) -> (f64, f64) {
+    points.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), p| {
+        let d = axis.dot(p.to_vec2());
+        (lo.min(d), hi.max(d))
+    })
+}
+
+fn rect_corners(rect: Rect) -> [Point; 4] {
+    [
+        Point::new(rect.x0, rect.y0),
+        Point::new(rect.x1, rect.y0),
+        Point::new(rect.x1, rect.y1),
+        Point::new(rect.x0, rect.y1),
+    ]
+}
+
+/// Separating axis test, `polygon` has to be convex.
+pub fn convex_polygon_intersects_rect(polygon: &[Point], rect: Rect) -> bool {
+    if polygon.is_empty() {
+        return false;
+    }
+    let (x0, x1) = project(polygon, Vec2::new(1.0, 0.0));
+    let (y0, y1) = project(polygon, Vec2::new(0.0, 1.0));
+    if x1 < rect.x0 || x0 > rect.x1 || y1 < rect.y0 || y0 > rect.y1 {
+        return false;
+    }
+    let corners = rect_corners(rect);
+    let n = polygon.len();
+    for i in 0..n {
+        let edge = polygon[(i + 1) % n] - polygon[i];
+        let axis = Vec2::new(-edge.y, edge.x);
+        let (a0, a1) = project(polygon, axis);
+        let (b0, b1) = project(&corners, axis);
+        if a1 < b0 || b1 < a0 {
+            return false;
+        }
+    }
+    return true;
+}
+
+fn segments_intersect(a: Point, b: Point, c: Point, d: Point) -> bool {
+    let d1 = cross(c, d, a);
+    let d2 = cross(c, d, b);
+    let d3 = cross(a, b, c);
+    let d4 = cross(a, b, d);
+    (d1 * d2 <= 0.0) && (d3 * d4 <= 0.0)
+}
+
+/// Exact test for arbitrary simple polygons.
+pub fn polygon_intersects_rect(polygon: &[Point], rect: Rect) -> bool {
+    if polygon.iter().any(|p| rect.contains(*p)) {
+        return true;
+    }
+    let corners = rect_corners(rect);
+    if crate::tiling_validation::polygon_contains(polygon, rect.center()) {
+        return true;
+    }
+    let n = polygon.len();
+    for i in 0..n {
+        let a = polygon[i];
+        let b = polygon[(i + 1) % n];
+        for j in 0..4 {
+            if segments_intersect(a, b, corners[j], corners[(j + 1) % 4]) {
+                return true;
+            }
+        }
+    }
+    return false;
+}
+
+fn grow(hull: &mut [Point], factor: f64) {
+    if hull.is_empty() {
+        return;
+    }
+    let center = hull.iter().fold(Vec2::ZERO, |sum, p| sum + p.to_vec2()) / hull.len() as f64;
+    for p in hull.iter_mut() {
+        *p = (center + (p.to_vec2() - center) * (1.0 + factor)).to_point();
+    }
+}
+
+impl TilingStep {
+    /// Convex hull of a rule's tile together with all of its descendants at any depth, in the
+    /// rule's own coordinates. `None` when the rules don't shrink, so there is no bounded hull.
+    pub fn descendant_hulls(&self) -> Vec<Option<Vec<Point>>> {
+        let mut hulls: Vec<Vec<Point>> = self
+            .rules
+            .iter()
+            .map(|rule| convex_hull(&rule.tile.control_points()))
+            .collect();
+        for _ in 0..HULL_ITERATIONS {
+            let mut change = 0f64;
+            let mut size = 0f64;
+            let next: Vec<Vec<Point>> = self
+                .rules
+                .iter()
+                .map(|rule| {
+                    let mut points = rule.tile.control_points();
+                    for child in &rule.result {
+                        if let Some(hull) = hulls.get(child.tile_id) {
+                            points.extend(hull.iter().map(|p| child.transform * *p));
+                        }
+                    }
+                    convex_hull(&points)
+                })
+                .collect();
+            for (old, new) in hulls.iter().zip(&next) {
+                let (x0, x1) = project(new, Vec2::new(1.0, 0.0));
+                let (y0, y1) = project(new, Vec2::new(0.0, 1.0));
+                size = size.max(x1 - x0).max(y1 - y0);
+                // Hulls only grow, so the new vertices furthest from the old hull tell how
+                // much changed.
+                for p in new {
+                    let distance = old
+                        .iter()
+                        .map(|q| q.distance(*p))
+                        .fold(f64::INFINITY, f64::min);
+                    change = change.max(distance);
+                }
+            }
+            hulls = next;
+            if !size.is_finite() {
+                break;
+            }
+            if change <= HULL_EPSILON * size {
+                return hulls
+                    .into_iter()
+                    .map(|mut hull| {
+                        grow(&mut hull, HULL_MARGIN);
+                        Some(hull)
+                    })
+                    .collect();
+            }
+        }
+        return vec![None; self.rules.len()];
+    }
+}
+
+/// Decides which parts of an expansion can affect a viewport.
+#[derive(Clone)]
+pub struct Culler {
+    bounds: Rect,
+    hulls: Vec<Option<Vec<Point>>>,
+}
+
+impl Culler {
+    pub fn new(step: &TilingStep, bounds: Rect) -> Culler {
+        Culler {
+            bounds,
+            hulls: step.descendant_hulls(),
+        }
+    }
+
+    /// Whether any descendant of the tile, at any depth, can intersect the bounds.
+    pub fn subtree_visible(&self, placement: &TilePlacement) -> bool {
+        match self.hulls.get(placement.tile_id) {
+            Some(Some(hull)) => {
+                let transformed: Vec<Point> =
+                    hull.iter().map(|p| placement.transform * *p).collect();
+                convex_polygon_intersects_rect(&transformed, self.bounds)
+            }
+            _ => true,
+        }
+    }
+
+    /// Whether every descendant of the tile, at any depth, lies inside `rect`.
+    pub fn subtree_inside(&self, placement: &TilePlacement, rect: Rect) -> bool {
+        match self.hulls.get(placement.tile_id) {
+            Some(Some(hull)) => hull.iter().all(|p| {
+                let p = placement.transform * *p;
+                rect.x0 < p.x && p.x < rect.x1 && rect.y0 < p.y && p.y < rect.y1
+            }),
+            _ => false,
+        }
+    }
+
+    pub fn tile_visible(&self, step: &TilingStep, placement: &TilePlacement) -> bool {
+        tile_intersects_rect(step, placement, self.bounds)
+    }
+}
+
+pub fn tile_intersects_rect(step: &TilingStep, placement: &TilePlacement, rect: Rect) -> bool {
+    let Some(rule) = step.rules.get(placement.tile_id) else {
+        return false;
+    };
+    let corners: Vec<Point> = if rule.tile.is_polygon() {
+        rule.tile
+            .corners
+            .iter()
+            .map(|p| placement.transform * *p)
+            .collect()
+    } else {
+        rule.tile
+            .outline_points()
+            .into_iter()
+            .map(|p| placement.transform * p)
+            .collect()
+    };
+    polygon_intersects_rect(&corners, rect)
+}
+
+#[cfg(test)]
+mod tests {
+    use kurbo::Affine;
+
+    use super::*;
+    use crate::presets;
+
+    fn seed() -> Vec<TilePlacement> {
+        vec![TilePlacement {
+            tile_id: 1,
+            transform: Affine::scale(10.0),
+        }]
+    }
+
+    #[test]
+    fn culling_only_drops_invisible_tiles() {
+        let tiling = presets::load_preset("socolar_5").unwrap();
+        let bounds = Rect::new(-2.0, 1.0, 3.0, 4.0);
+        let mut all = Vec::new();
+        tiling.expand_levels(&seed(), 4, &mut all, None);
+        all.retain(|tile| tile_intersects_rect(&tiling, tile, bounds));
+        let mut culled = Vec::new();
+        let result = tiling.expand_bound(&seed(), 4, bounds, &mut culled, None);
+        assert!(!culled.is_empty());
+        assert_eq!(result.tiles, all.len());
+        assert_eq!(culled.len(), all.len());
+        for (a, b) in culled.iter().zip(&all) {
+            assert_eq!(a.tile_id, b.tile_id);
+            assert_eq!(a.transform.as_coeffs(), b.transform.as_coeffs());
+        }
+    }
+
+    #[test]
+    fn hulls_contain_every_descendant() {
+        let tiling = presets::load_preset("socolar_5").unwrap();
+        let hulls = tiling.descendant_hulls();
+        for (tile_id, hull) in hulls.iter().enumerate() {
+            let hull = hull.as_ref().unwrap();
+            let n = hull.len();
+            let root = vec![TilePlacement {
+                tile_id,
+                transform: Affine::IDENTITY,
+            }];
+            for levels in 0..5 {
+                let mut tiles = Vec::new();
+                tiling.expand_levels(&root, levels, &mut tiles, None);
+                for tile in &tiles {
+                    for p in tiling.rules[tile.tile_id].tile.control_points() {
+                        let p = tile.transform * p;
+                        // Counter-clockwise, so inside is left of every edge.
+                        let left = |i: usize| cross(hull[i], hull[(i + 1) % n], p) >= -1e-9;
+                        let inside = (0..n).all(left);
+                        assert!(inside, "rule {tile_id}, level {levels}: {p:?} outside");
+                    }
+                }
+            }
+        }
+    }
+
+    fn polygon(points: &[(f64, f64)]) -> Vec<Point> {
+        return points.iter().map(|&p| p.into()).collect();
+    }
+
+    #[test]
+    fn touching_polygons_intersect() {
+        let rect = Rect::new(0.0, 0.0, 1.0, 1.0);
+        let edge = polygon(&[(1.0, 0.0), (2.0, 0.0), (2.0, 1.0), (1.0, 1.0)]);
+        assert!(convex_polygon_intersects_rect(&edge, rect));
+        let corner = polygon(&[(1.0, 1.0), (2.0, 1.0), (2.0, 2.0)]);
+        assert!(convex_polygon_intersects_rect(&corner, rect));
+        let gap = polygon(&[(1.01, 0.0), (2.0, 0.0), (2.0, 1.0), (1.01, 1.0)]);
+        assert!(!convex_polygon_intersects_rect(&gap, rect));
+        // Overlaps the bounding box of the rectangle but is separated by its long edge.
+        let diagonal = polygon(&[(2.0, 0.5), (2.0, 2.0), (0.5, 2.0)]);
+        assert!(!convex_polygon_intersects_rect(&diagonal, rect));
+        let inside = polygon(&[(0.2, 0.2), (0.8, 0.2), (0.5, 0.8)]);
+        assert!(convex_polygon_intersects_rect(&inside, rect));
+    }
+}
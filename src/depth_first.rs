@@ -0,0 +1,187 @@
+use kurbo::Rect;
+
+use crate::culling::Culler;
+use crate::tiling::*;
+
+struct Frame {
+    placement: TilePlacement,
+    depth: usize,
+    next_child: usize,
+}
+
+/// Lazily yields the final level tiles of an expansion in depth-first order. Memory use is
+/// proportional to the number of levels instead of the number of tiles. Subtrees and final tiles
+/// are culled with the same tests as `TilingStep::expand_bound`.
+pub struct DepthFirstExpansion<'a> {
+    step: &'a TilingStep,
+    levels: usize,
+    culler: Option<Culler>,
+    roots: std::vec::IntoIter<TilePlacement>,
+    stack: Vec<Frame>,
+}
+
+impl<'a> DepthFirstExpansion<'a> {
+    fn visible(&self, placement: &TilePlacement, depth: usize) -> bool {
+        match &self.culler {
+            Some(culler) if depth < self.levels => culler.subtree_visible(placement),
+            Some(culler) => culler.tile_visible(self.step, placement),
+            None => true,
+        }
+    }
+
+    fn push(&mut self, placement: TilePlacement, depth: usize) {
+        if self.visible(&placement, depth) {
+            self.stack.push(Frame {
+                placement,
+                depth,
+                next_child: 0,
+            });
+        }
+    }
+}
+
+impl<'a> Iterator for DepthFirstExpansion<'a> {
+    type Item = TilePlacement;
+
+    fn next(&mut self) -> Option<TilePlacement> {
+        loop {
+            let Some(top) = self.stack.last_mut() else {
+                let root = self.roots.next()?;
+                self.push(root, 0);
+                continue;
+            };
+            if top.depth >= self.levels {
+                return self.stack.pop().map(|frame| frame.placement);
+            }
+            let child = self
+                .step
+                .rules
+                .get(top.placement.tile_id)
+                .and_then(|rule| rule.result.get(top.next_child));
+            let Some(child) = child else {
+                self.stack.pop();
+                continue;
+            };
+            top.next_child += 1;
+            let placement = TilePlacement {
+                tile_id: child.tile_id,
+                transform: top.placement.transform * child.transform,
+            };
+            let depth = top.depth + 1;
+            self.push(placement, depth);
+        }
+    }
+}
+
+impl TilingStep {
+    pub fn iter_expand(
+        &self,
+        input: Vec<TilePlacement>,
+        levels: usize,
+        bounds: Option<Rect>,
+    ) -> DepthFirstExpansion<'_> {
+        let culler = bounds.map(|bounds| Culler::new(self, bounds));
+        self.iter_expand_culled(input, levels, culler)
+    }
+
+    /// Same as `iter_expand` reusing an existing culler, which saves recomputing the descendant
+    /// hulls for every subtree.
+    pub(crate) fn iter_expand_culled(
+        &self,
+        input: Vec<TilePlacement>,
+        levels: usize,
+        culler: Option<Culler>,
+    ) -> DepthFirstExpansion<'_> {
+        DepthFirstExpansion {
+            step: self,
+            levels,
+            culler,
+            roots: input.into_iter(),
+            stack: Vec::with_capacity(levels + 1),
+        }
+    }
+
+    pub fn iter_expand_seed(
+        &self,
+        seed: &Seed,
+        levels: usize,
+        initial_scale: f64,
+        bounds: Option<Rect>,
+    ) -> DepthFirstExpansion<'_> {
+        self.iter_expand(self.seed_placements(seed, initial_scale), levels, bounds)
+    }
+
+    pub fn try_iter_expand_seed(
+        &self,
+        seed: &Seed,
+        levels: usize,
+        initial_scale: f64,
+        bounds: Option<Rect>,
+    ) -> Result<DepthFirstExpansion<'_>, TilingError> {
+        self.validate()?;
+        self.validate_seed(seed)?;
+        return Ok(self.iter_expand_seed(seed, levels, initial_scale, bounds));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kurbo::Affine;
+
+    use super::*;
+    use crate::presets;
+
+    fn seed() -> Vec<TilePlacement> {
+        vec![TilePlacement {
+            tile_id: 1,
+            transform: Affine::scale(10.0),
+        }]
+    }
+
+    /// Tiles in a canonical order, with coordinates rounded to absorb the different order of
+    /// the transform products.
+    fn multiset(tiles: impl IntoIterator<Item = TilePlacement>) -> Vec<(usize, [i64; 6])> {
+        let mut result: Vec<(usize, [i64; 6])> = tiles
+            .into_iter()
+            .map(|tile| {
+                let coeffs = tile.transform.as_coeffs().map(|x| (x * 1e6).round() as i64);
+                (tile.tile_id, coeffs)
+            })
+            .collect();
+        result.sort();
+        return result;
+    }
+
+    #[test]
+    fn matches_breadth_first() {
+        let tiling = presets::load_preset("socolar_5").unwrap();
+        let levels = 4;
+        let mut all = Vec::new();
+        tiling.expand_levels(&seed(), levels, &mut all, None);
+        let streamed = tiling.iter_expand(seed(), levels, None);
+        assert_eq!(multiset(streamed), multiset(all));
+
+        let bounds = Rect::new(-3.0, -2.0, 4.0, 5.0);
+        let mut visible = Vec::new();
+        tiling.expand_bound(&seed(), levels, bounds, &mut visible, None);
+        assert!(!visible.is_empty());
+        let visible = multiset(visible);
+        let streamed = tiling.iter_expand(seed(), levels, Some(bounds));
+        assert_eq!(multiset(streamed), visible);
+        let culler = Some(Culler::new(&tiling, bounds));
+        let streamed = tiling.iter_expand_culled(seed(), levels, culler);
+        assert_eq!(multiset(streamed), visible);
+    }
+
+    #[test]
+    fn stack_depth_is_bounded_by_levels() {
+        let tiling = presets::load_preset("socolar_5").unwrap();
+        let levels = 5;
+        let mut expansion = tiling.iter_expand(seed(), levels, None);
+        let mut deepest = 0;
+        while expansion.next().is_some() {
+            deepest = deepest.max(expansion.stack.len());
+        }
+        assert!(deepest <= levels + 1, "stack grew to {deepest}");
+    }
+}
@@ -0,0 +1,184 @@
+use kurbo::Point;
+
+use crate::tiling::*;
+
+/// Edge shapes this close in edge coordinates count as the same.
+const MATCH_TOLERANCE: f64 = 1e-9;
+
+/// One edge of a prototile, edge i of a tile runs from corner i to corner i + 1.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EdgeRef {
+    pub rule: usize,
+    pub edge: usize,
+}
+
+/// Two prototile edges that meet in the tiling and so have to be the same curve. `reversed` when
+/// they run in opposite directions along the shared edge, which is the usual case for two
+/// neighbouring tiles with the same winding.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EdgeMatch {
+    pub a: EdgeRef,
+    pub b: EdgeRef,
+    pub reversed: bool,
+}
+
+impl EdgeShape {
+    /// The same curve seen from the other end of the edge.
+    pub fn reversed(&self) -> EdgeShape {
+        let flip = |p: Point| Point::new(1.0 - p.x, -p.y);
+        match *self {
+            EdgeShape::Straight => EdgeShape::Straight,
+            EdgeShape::Quadratic(p) => EdgeShape::Quadratic(flip(p)),
+            EdgeShape::Cubic(p1, p2) => EdgeShape::Cubic(flip(p2), flip(p1)),
+            EdgeShape::Arc { bulge } => EdgeShape::Arc { bulge: -bulge },
+        }
+    }
+
+    fn approx_eq(&self, other: &EdgeShape) -> bool {
+        let close = |a: Point, b: Point| (a - b).hypot() <= MATCH_TOLERANCE;
+        match (*self, *other) {
+            (a, b) if a.is_straight() && b.is_straight() => true,
+            (EdgeShape::Quadratic(a), EdgeShape::Quadratic(b)) => close(a, b),
+            (EdgeShape::Cubic(a1, a2), EdgeShape::Cubic(b1, b2)) => close(a1, b1) && close(a2, b2),
+            (EdgeShape::Arc { bulge: a }, EdgeShape::Arc { bulge: b }) => {
+                (a - b).abs() <= MATCH_TOLERANCE
+            }
+            _ => false,
+        }
+    }
+}
+
+impl EdgeMatch {
+    /// Shape `to` needs when `from` has `shape`, `to` being the other edge of the match.
+    fn map_shape(&self, shape: EdgeShape) -> EdgeShape {
+        if self.reversed {
+            return shape.reversed();
+        }
+        return shape;
+    }
+}
+
+impl TilingStep {
+    pub fn edge_shape(&self, edge: EdgeRef) -> Option<EdgeShape> {
+        let tile = &self.rules.get(edge.rule)?.tile;
+        if edge.edge >= tile.corners.len() {
+            return None;
+        }
+        return Some(tile.edge(edge.edge));
+    }
+
+    /// Sets the shape of an edge and of every edge matched to it, directly or through other
+    /// matches. Edges reached twice keep the first shape, so an edge matched to itself is left
+    /// as given.
+    pub fn set_edge_shape(&mut self, edge: EdgeRef, shape: EdgeShape) {
+        let mut visited: Vec<EdgeRef> = Vec::new();
+        let mut pending = vec![(edge, shape)];
+        while let Some((edge, shape)) = pending.pop() {
+            if visited.contains(&edge) || self.edge_shape(edge).is_none() {
+                continue;
+            }
+            visited.push(edge);
+            *self.rules[edge.rule].tile.edge_mut(edge.edge) = shape;
+            for matched in &self.edge_matches {
+                if matched.a == edge {
+                    pending.push((matched.b, matched.map_shape(shape)));
+                }
+                if matched.b == edge {
+                    pending.push((matched.a, matched.map_shape(shape)));
+                }
+            }
+        }
+    }
+
+    /// Every matched edge has to exist, e.g. after loading a file or removing corners.
+    pub fn validate_edge_matches(&self) -> Result<(), TilingError> {
+        for (index, matched) in self.edge_matches.iter().enumerate() {
+            for edge in [matched.a, matched.b] {
+                if self.edge_shape(edge).is_none() {
+                    return Err(TilingError::InvalidEdgeMatch {
+                        index,
+                        rule: edge.rule,
+                        edge: edge.edge,
+                    });
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// Whether both edges of a match exist and have the same curve.
+    pub fn edge_match_holds(&self, matched: &EdgeMatch) -> bool {
+        match (self.edge_shape(matched.a), self.edge_shape(matched.b)) {
+            (Some(a), Some(b)) => matched.map_shape(a).approx_eq(&b),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets;
+
+    fn edge(rule: usize, edge: usize) -> EdgeRef {
+        return EdgeRef { rule, edge };
+    }
+
+    fn matched(a: EdgeRef, b: EdgeRef, reversed: bool) -> EdgeMatch {
+        return EdgeMatch { a, b, reversed };
+    }
+
+    #[test]
+    fn reversed_mirrors_control_points() {
+        let quad = EdgeShape::Quadratic(Point::new(0.3, 0.2));
+        assert_eq!(quad.reversed(), EdgeShape::Quadratic(Point::new(0.7, -0.2)));
+        let cubic = EdgeShape::Cubic(Point::new(0.25, 0.125), Point::new(0.5, -0.25));
+        let flipped = EdgeShape::Cubic(Point::new(0.5, 0.25), Point::new(0.75, -0.125));
+        assert_eq!(cubic.reversed(), flipped);
+        let arc = EdgeShape::Arc { bulge: 0.2 };
+        assert_eq!(arc.reversed(), EdgeShape::Arc { bulge: -0.2 });
+        assert_eq!(EdgeShape::Straight.reversed(), EdgeShape::Straight);
+        for shape in [quad, cubic] {
+            assert!(shape.reversed().reversed().approx_eq(&shape));
+        }
+    }
+
+    #[test]
+    fn shapes_follow_matches() {
+        let mut tiling = presets::load_preset("socolar_5").unwrap();
+        tiling.edge_matches = vec![
+            matched(edge(0, 0), edge(1, 2), true),
+            matched(edge(1, 2), edge(0, 3), false),
+        ];
+        let shape = EdgeShape::Quadratic(Point::new(0.4, 0.1));
+        tiling.set_edge_shape(edge(0, 0), shape);
+
+        assert_eq!(tiling.edge_shape(edge(0, 0)), Some(shape));
+        assert_eq!(tiling.edge_shape(edge(1, 2)), Some(shape.reversed()));
+        assert_eq!(tiling.edge_shape(edge(0, 3)), Some(shape.reversed()));
+        assert_eq!(tiling.edge_shape(edge(0, 1)), Some(EdgeShape::Straight));
+        for m in &tiling.edge_matches {
+            assert!(tiling.edge_match_holds(m));
+        }
+    }
+
+    #[test]
+    fn rejects_missing_edges() {
+        let mut tiling = presets::load_preset("socolar_5").unwrap();
+        let corners = tiling.rules[0].tile.corners.len();
+        for (rule, missing) in [(5, 0), (0, corners)] {
+            tiling.edge_matches = vec![
+                matched(edge(0, 0), edge(1, 0), true),
+                matched(edge(1, 1), edge(rule, missing), false),
+            ];
+            let expected = TilingError::InvalidEdgeMatch {
+                index: 1,
+                rule,
+                edge: missing,
+            };
+            assert_eq!(tiling.validate_edge_matches(), Err(expected.clone()));
+            assert_eq!(tiling.validate(), Err(expected));
+            assert!(!tiling.edge_match_holds(&tiling.edge_matches[1]));
+        }
+    }
+}
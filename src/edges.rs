@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+use std::f64::consts::{FRAC_PI_2, PI};
+
+use kurbo::{BezPath, Line, ParamCurve, PathSeg, Point, Vec2};
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DedupStats {
+    pub input_segments: usize,
+    pub output_segments: usize,
+}
+
+impl DedupStats {
+    pub fn removed(&self) -> usize {
+        self.input_segments - self.output_segments
+    }
+}
+
+/// Splits a path into straight segments, everything else is returned separately untouched.
+pub fn path_segments(path: &BezPath) -> (Vec<Line>, Vec<PathSeg>) {
+    let mut lines = Vec::new();
+    let mut other = Vec::new();
+    for seg in path.segments() {
+        match seg {
+            PathSeg::Line(line) => lines.push(line),
+            _ => other.push(seg),
+        }
+    }
+    return (lines, other);
+}
+
+pub fn segments_to_path(lines: &[Line], other: &[PathSeg]) -> BezPath {
+    let mut result = BezPath::new();
+    for line in lines {
+        result.move_to(line.p0);
+        result.line_to(line.p1);
+    }
+    for seg in other {
+        result.move_to(seg.start());
+        match seg {
+            PathSeg::Line(line) => result.line_to(line.p1),
+            PathSeg::Quad(quad) => result.quad_to(quad.p1, quad.p2),
+            PathSeg::Cubic(cubic) => result.curve_to(cubic.p1, cubic.p2, cubic.p3),
+        }
+    }
+    return result;
+}
+
+struct DirectedSegment {
+    index: usize,
+    angle: f64,
+    dir: Vec2,
+}
+
+/// A segment projected onto the line of its `CollinearGroup`.
+pub struct LineInterval {
+    /// Index of the segment given to `collinear_groups`.
+    pub index: usize,
+    /// Position of the segment across the line, along `CollinearGroup::normal`.
+    pub offset: f64,
+    pub start: f64,
+    pub end: f64,
+}
+
+/// Segments on the same line within a tolerance.
+pub struct CollinearGroup {
+    pub dir: Vec2,
+    pub normal: Vec2,
+    /// Sorted by `start`.
+    pub intervals: Vec<LineInterval>,
+}
+
+/// Groups segments that are collinear within `tolerance`, first by direction and then by their
+/// offset across that direction. Segments no longer than `tolerance` have no meaningful
+/// direction and are left out.
+pub fn collinear_groups(lines: &[Line], tolerance: f64) -> Vec<CollinearGroup> {
+    let mut directed = Vec::with_capacity(lines.len());
+    let mut max_length = 0f64;
+    for (index, line) in lines.iter().enumerate() {
+        let delta = line.p1 - line.p0;
+        let length = delta.hypot();
+        if length <= tolerance {
+            continue;
+        }
+        max_length = max_length.max(length);
+        let mut dir = delta / length;
+        if dir.x < 0.0 || (dir.x == 0.0 && dir.y < 0.0) {
+            dir = -dir;
+        }
+        directed.push(DirectedSegment {
+            index,
+            angle: dir.y.atan2(dir.x),
+            dir,
+        });
+    }
+
+    // Two segments of length L whose angles differ by less than this deviate by at most
+    // `tolerance` at the far end.
+    let angle_tolerance = if max_length > 0.0 {
+        tolerance / max_length
+    } else {
+        0.0
+    };
+    for segment in &mut directed {
+        // Nearly vertical segments pointing down end up at -pi/2, move them next to +pi/2.
+        if segment.angle < -FRAC_PI_2 + angle_tolerance {
+            segment.angle += PI;
+            segment.dir = -segment.dir;
+        }
+    }
+    directed.sort_by(|a, b| a.angle.total_cmp(&b.angle));
+
+    let mut groups = Vec::new();
+    let mut group_start = 0;
+    for i in 1..=directed.len() {
+        if i < directed.len() && directed[i].angle - directed[i - 1].angle <= angle_tolerance {
+            continue;
+        }
+        split_parallel(lines, &directed[group_start..i], tolerance, &mut groups);
+        group_start = i;
+    }
+    return groups;
+}
+
+/// Splits segments of the same direction into groups on the same line.
+fn split_parallel(
+    lines: &[Line],
+    parallel: &[DirectedSegment],
+    tolerance: f64,
+    groups: &mut Vec<CollinearGroup>,
+) {
+    let Some(first) = parallel.first() else {
+        return;
+    };
+    let dir = first.dir;
+    let normal = Vec2::new(-dir.y, dir.x);
+    let mut intervals: Vec<LineInterval> = parallel
+        .iter()
+        .map(|segment| {
+            let line = &lines[segment.index];
+            let t0 = dir.dot(line.p0.to_vec2());
+            let t1 = dir.dot(line.p1.to_vec2());
+            LineInterval {
+                index: segment.index,
+                offset: normal.dot(line.p0.midpoint(line.p1).to_vec2()),
+                start: t0.min(t1),
+                end: t0.max(t1),
+            }
+        })
+        .collect();
+    intervals.sort_by(|a, b| a.offset.total_cmp(&b.offset));
+
+    let starts: Vec<usize> = (0..intervals.len())
+        .filter(|&i| i == 0 || intervals[i].offset - intervals[i - 1].offset > tolerance)
+        .collect();
+    // Split from the back so every interval moves once, then restore the order of the lines.
+    let first_group = groups.len();
+    for &start in starts.iter().rev() {
+        let mut line = intervals.split_off(start);
+        line.sort_by(|a, b| a.start.total_cmp(&b.start));
+        groups.push(CollinearGroup {
+            dir,
+            normal,
+            intervals: line,
+        });
+    }
+    groups[first_group..].reverse();
+}
+
+/// Merges collinear segments that overlap by more than `tolerance`, including partial overlaps.
+/// Segments that only touch at the ends are kept apart.
+pub fn dedup_segments(lines: &[Line], tolerance: f64) -> (Vec<Line>, DedupStats) {
+    // Too short to have a meaningful direction.
+    let mut result: Vec<Line> = lines
+        .iter()
+        .filter(|line| (line.p1 - line.p0).hypot() <= tolerance)
+        .copied()
+        .collect();
+    for group in collinear_groups(lines, tolerance) {
+        merge_collinear(lines, &group, tolerance, &mut result);
+    }
+    let stats = DedupStats {
+        input_segments: lines.len(),
+        output_segments: result.len(),
+    };
+    return (result, stats);
+}
+
+fn merge_collinear(lines: &[Line], group: &CollinearGroup, tolerance: f64, output: &mut Vec<Line>) {
+    let intervals = &group.intervals;
+    let offset = intervals.iter().map(|x| x.offset).sum::<f64>() / intervals.len().max(1) as f64;
+    let to_point = |t: f64| (group.dir * t + group.normal * offset).to_point();
+
+    let mut i = 0;
+    while i < intervals.len() {
+        let mut end = intervals[i].end;
+        let mut j = i + 1;
+        while j < intervals.len() && intervals[j].start < end - tolerance {
+            end = end.max(intervals[j].end);
+            j += 1;
+        }
+        if j == i + 1 {
+            output.push(lines[intervals[i].index]);
+        } else {
+            output.push(Line::new(to_point(intervals[i].start), to_point(end)));
+        }
+        i = j;
+    }
+}
+
+fn control_points(seg: &PathSeg) -> Vec<Point> {
+    match seg {
+        PathSeg::Line(line) => vec![line.p0, line.p1],
+        PathSeg::Quad(quad) => vec![quad.p0, quad.p1, quad.p2],
+        PathSeg::Cubic(cubic) => vec![cubic.p0, cubic.p1, cubic.p2, cubic.p3],
+    }
+}
+
+/// Same kind of segment with control points within `tolerance`, in either direction.
+fn same_curve(a: &PathSeg, b: &PathSeg, tolerance: f64) -> bool {
+    let (a, b) = (control_points(a), control_points(b));
+    let close = |(p, q): (&Point, &Point)| p.distance(*q) <= tolerance;
+    a.len() == b.len() && (a.iter().zip(&b).all(close) || a.iter().zip(b.iter().rev()).all(close))
+}
+
+/// Drops curves that repeat an earlier one, e.g. a curved edge drawn by both tiles. Curves are
+/// only compared with those whose midpoint lies in a neighbouring cell of a `tolerance` grid.
+pub fn dedup_curves(curves: &[PathSeg], tolerance: f64) -> Vec<PathSeg> {
+    let size = tolerance.max(f64::EPSILON);
+    let cell = |p: Point| ((p.x / size).floor() as i64, (p.y / size).floor() as i64);
+    let mut grid: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    let mut result: Vec<PathSeg> = Vec::new();
+    for seg in curves {
+        // Midpoints of matching curves are at most `tolerance` apart.
+        let (x, y) = cell(seg.eval(0.5));
+        let duplicate = (x - 1..=x + 1)
+            .flat_map(|x| (y - 1..=y + 1).map(move |y| (x, y)))
+            .filter_map(|key| grid.get(&key))
+            .flatten()
+            .any(|&i| same_curve(&result[i], seg, tolerance));
+        if !duplicate {
+            grid.entry((x, y)).or_default().push(result.len());
+            result.push(*seg);
+        }
+    }
+    return result;
+}
+
+pub fn dedup_path(path: &BezPath, tolerance: f64) -> (BezPath, DedupStats) {
+    let (lines, curves) = path_segments(path);
+    let (lines, mut stats) = dedup_segments(&lines, tolerance);
+    let deduped = dedup_curves(&curves, tolerance);
+    stats.input_segments += curves.len();
+    stats.output_segments += deduped.len();
+    return (segments_to_path(&lines, &deduped), stats);
+}
+
+#[cfg(test)]
+mod tests {
+    use kurbo::QuadBez;
+
+    use super::*;
+
+    const TOLERANCE: f64 = 1e-3;
+
+    fn dedup(lines: &[Line]) -> Vec<Line> {
+        return dedup_segments(lines, TOLERANCE).0;
+    }
+
+    fn has_line(lines: &[Line], a: impl Into<Point>, b: impl Into<Point>) -> bool {
+        let (a, b) = (a.into(), b.into());
+        lines.iter().any(|line| {
+            (line.p0.distance(a) < TOLERANCE && line.p1.distance(b) < TOLERANCE)
+                || (line.p0.distance(b) < TOLERANCE && line.p1.distance(a) < TOLERANCE)
+        })
+    }
+
+    #[test]
+    fn shared_grid_edges_collapse() {
+        let mut path = BezPath::new();
+        for (x, y) in [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)] {
+            path.move_to((x, y));
+            path.line_to((x, y + 1.0));
+            path.line_to((x + 1.0, y + 1.0));
+            path.line_to((x + 1.0, y));
+            path.close_path();
+        }
+        let (deduped, stats) = dedup_path(&path, TOLERANCE);
+        assert_eq!(stats.input_segments, 16);
+        // Three lines of two unit edges in each direction.
+        assert_eq!(stats.output_segments, 12);
+        assert_eq!(stats.removed(), 4);
+        assert_eq!(deduped.segments().count(), 12);
+    }
+
+    #[test]
+    fn partial_overlaps_merge() {
+        let a = Line::new((0.0, 0.0), (2.0, 0.0));
+        let b = Line::new((3.0, 0.0), (1.0, 0.0));
+        let merged = dedup(&[a, b]);
+        assert_eq!(merged.len(), 1);
+        assert!(has_line(&merged, (0.0, 0.0), (3.0, 0.0)));
+    }
+
+    #[test]
+    fn touching_segments_stay_apart() {
+        let a = Line::new((0.0, 0.0), (1.0, 1.0));
+        let b = Line::new((1.0, 1.0), (2.0, 2.0));
+        let c = Line::new((0.0, 0.5), (1.0, 1.5));
+        let lines = dedup(&[a, b, c]);
+        assert_eq!(lines.len(), 3);
+        for line in [a, b, c] {
+            assert!(has_line(&lines, line.p0, line.p1));
+        }
+    }
+
+    #[test]
+    fn near_vertical_segments_group_across_the_wrap() {
+        // Canonical angles just below pi/2 and just above -pi/2.
+        let a = Line::new((0.0, 0.0), (1e-6, 1.0));
+        let b = Line::new((0.0, 1.5), (1e-6, 0.5));
+        let merged = dedup(&[a, b]);
+        assert_eq!(merged.len(), 1);
+        assert!(has_line(&merged, (0.0, 0.0), (0.0, 1.5)));
+    }
+
+    #[test]
+    fn repeated_curves_are_drawn_once() {
+        let curve = QuadBez::new((0.0, 0.0), (0.5, 0.3), (1.0, 0.0));
+        let reversed = QuadBez::new(curve.p2, curve.p1, curve.p0);
+        let mirrored = QuadBez::new(curve.p0, (0.5, -0.3), curve.p2);
+        let curves = [curve, reversed, mirrored].map(PathSeg::Quad);
+        let deduped = dedup_curves(&curves, TOLERANCE);
+        assert_eq!(deduped.len(), 2);
+        assert!(same_curve(&deduped[0], &curves[0], 0.0));
+        assert!(same_curve(&deduped[1], &curves[2], 0.0));
+    }
+}
@@ -0,0 +1,238 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use kurbo::Rect;
+use rayon::prelude::*;
+
+use crate::culling::Culler;
+use crate::progress::Progress;
+use crate::tiling::*;
+
+/// Tiles expanded between cancellation checks and progress updates.
+const CHUNK_SIZE: usize = 4096;
+
+/// How `TilingStep::expand_breadth_first` runs.
+pub struct LevelOptions<'a> {
+    /// Stops at the last level whose tiles fit, counting every child before culling.
+    pub max_tiles: Option<usize>,
+    /// Spreads every level over the thread pool. The output doesn't change.
+    pub parallel: bool,
+    pub progress: &'a Progress,
+}
+
+impl TilingStep {
+    /// The level loop behind every breadth-first expansion. Each level replaces the tiles that
+    /// `expand` accepts by their children, in order, and drops the others. Every child gets the
+    /// payload `child` derives from its parent's payload and its index in `TilingRule::result`.
+    /// `on_level` sees the input and every completed level. Fails only when cancelled.
+    pub fn expand_breadth_first<T: Send + Sync>(
+        &self,
+        input: Vec<(TilePlacement, T)>,
+        levels: usize,
+        options: &LevelOptions,
+        expand: impl Fn(&TilePlacement) -> bool + Sync,
+        child: impl Fn(&T, usize) -> T + Sync,
+        mut on_level: impl FnMut(usize, &[(TilePlacement, T)]),
+    ) -> Result<(Vec<(TilePlacement, T)>, ExpansionResult), TilingError> {
+        let mut frontier = input;
+        let mut result = ExpansionResult::default();
+        on_level(0, &frontier);
+        for _ in 0..levels {
+            options.progress.set_tiles(0);
+            match self.expand_level(&frontier, options, &expand, &child)? {
+                Some(next) => frontier = next,
+                None => {
+                    result.truncated = true;
+                    break;
+                }
+            }
+            result.level_reached += 1;
+            options.progress.set_level(result.level_reached);
+            on_level(result.level_reached, &frontier);
+        }
+        result.tiles = frontier.len();
+        return Ok((frontier, result));
+    }
+
+    /// Children of the accepted tiles, `None` when there are more than `max_tiles`.
+    fn expand_level<T: Send + Sync>(
+        &self,
+        frontier: &[(TilePlacement, T)],
+        options: &LevelOptions,
+        expand: &(impl Fn(&TilePlacement) -> bool + Sync),
+        child: &(impl Fn(&T, usize) -> T + Sync),
+    ) -> Result<Option<Vec<(TilePlacement, T)>>, TilingError> {
+        let produced = AtomicUsize::new(0);
+        let over_limit = AtomicBool::new(false);
+        let expand_chunk = |chunk: &[(TilePlacement, T)]| {
+            let mut part = Vec::new();
+            if options.progress.is_cancelled() || over_limit.load(Ordering::Relaxed) {
+                return part;
+            }
+            for (tile, payload) in chunk {
+                if !expand(tile) {
+                    continue;
+                }
+                let rule = &self.rules[tile.tile_id];
+                for (i, item) in rule.result.iter().enumerate() {
+                    let placement = TilePlacement {
+                        tile_id: item.tile_id,
+                        transform: tile.transform * item.transform,
+                    };
+                    part.push((placement, child(payload, i)));
+                }
+            }
+            let total = produced.fetch_add(part.len(), Ordering::Relaxed) + part.len();
+            options.progress.report_tiles(total);
+            if options.max_tiles.map_or(false, |limit| total > limit) {
+                over_limit.store(true, Ordering::Relaxed);
+            }
+            part
+        };
+        let parts: Vec<Vec<(TilePlacement, T)>> = if options.parallel {
+            frontier.par_chunks(CHUNK_SIZE).map(&expand_chunk).collect()
+        } else {
+            frontier.chunks(CHUNK_SIZE).map(&expand_chunk).collect()
+        };
+        options.progress.check()?;
+        if over_limit.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        let mut next = Vec::with_capacity(produced.load(Ordering::Relaxed));
+        for mut part in parts {
+            next.append(&mut part);
+        }
+        return Ok(Some(next));
+    }
+
+    /// `expand_breadth_first` without payload or cancellation, for the plain expansions.
+    pub fn expand_placements(
+        &self,
+        input: &[TilePlacement],
+        levels: usize,
+        max_tiles: Option<usize>,
+        parallel: bool,
+        expand: impl Fn(&TilePlacement) -> bool + Sync,
+    ) -> (Vec<TilePlacement>, ExpansionResult) {
+        let progress = Progress::default();
+        let options = LevelOptions {
+            max_tiles,
+            parallel,
+            progress: &progress,
+        };
+        let input = input.iter().map(|tile| (tile.clone(), ())).collect();
+        let expanded =
+            self.expand_breadth_first(input, levels, &options, expand, |_, _| (), |_, _| {});
+        let (tiles, result) = expanded.expect("expansions without a progress can't be cancelled");
+        return (tiles.into_iter().map(|(tile, _)| tile).collect(), result);
+    }
+
+    /// Same tiles in the same order as `expand_bound`, or `expand_levels` without bounds, with
+    /// progress and cancellation through `options`.
+    pub fn expand_visible(
+        &self,
+        input: &[TilePlacement],
+        levels: usize,
+        bounds: Option<Rect>,
+        options: &LevelOptions,
+    ) -> Result<(Vec<TilePlacement>, ExpansionResult), TilingError> {
+        let culler = bounds.map(|bounds| Culler::new(self, bounds));
+        let input = input.iter().map(|tile| (tile.clone(), ())).collect();
+        let expand = |tile: &TilePlacement| {
+            culler
+                .as_ref()
+                .map_or(true, |culler| culler.subtree_visible(tile))
+        };
+        let (tiles, mut result) =
+            self.expand_breadth_first(input, levels, options, expand, |_, _| (), |_, _| {})?;
+        let tiles: Vec<TilePlacement> = tiles
+            .into_iter()
+            .map(|(tile, _)| tile)
+            .filter(|tile| {
+                culler
+                    .as_ref()
+                    .map_or(true, |culler| culler.tile_visible(self, tile))
+            })
+            .collect();
+        result.tiles = tiles.len();
+        return Ok((tiles, result));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kurbo::Affine;
+
+    use super::*;
+    use crate::presets;
+
+    fn seed() -> Vec<TilePlacement> {
+        vec![TilePlacement {
+            tile_id: 0,
+            transform: Affine::IDENTITY,
+        }]
+    }
+
+    /// Compares the serial expansions with `expand_visible` on the thread pool.
+    fn assert_same_expansion(
+        tiling: &TilingStep,
+        levels: usize,
+        bounds: Option<Rect>,
+        max_tiles: Option<usize>,
+    ) -> ExpansionResult {
+        let mut serial = Vec::new();
+        let serial_result = match bounds {
+            Some(bounds) => tiling.expand_bound(&seed(), levels, bounds, &mut serial, max_tiles),
+            None => tiling.expand_levels(&seed(), levels, &mut serial, max_tiles),
+        };
+        let progress = Progress::default();
+        let options = LevelOptions {
+            max_tiles,
+            parallel: true,
+            progress: &progress,
+        };
+        let (parallel, parallel_result) = tiling
+            .expand_visible(&seed(), levels, bounds, &options)
+            .unwrap();
+        assert_eq!(serial_result.tiles, parallel_result.tiles);
+        assert_eq!(serial_result.level_reached, parallel_result.level_reached);
+        assert_eq!(serial_result.truncated, parallel_result.truncated);
+        assert_eq!(serial.len(), parallel.len());
+        for (a, b) in serial.iter().zip(&parallel) {
+            assert_eq!(a.tile_id, b.tile_id);
+            assert_eq!(a.transform.as_coeffs(), b.transform.as_coeffs());
+        }
+        return serial_result;
+    }
+
+    #[test]
+    fn parallel_matches_serial() {
+        let tiling = presets::load_preset("socolar_5").unwrap();
+        assert_same_expansion(&tiling, 5, None, None);
+        let bounds = Rect::new(-2.0, -1.0, 3.0, 2.0);
+        assert_same_expansion(&tiling, 6, Some(bounds), None);
+    }
+
+    #[test]
+    fn parallel_stops_at_the_same_level() {
+        let tiling = presets::load_preset("socolar_5").unwrap();
+        let mut tiles = Vec::new();
+        let limit = tiling.expand_levels(&seed(), 3, &mut tiles, None).tiles;
+        let result = assert_same_expansion(&tiling, 6, None, Some(limit));
+        assert!(result.truncated);
+        assert_eq!(result.level_reached, 3);
+    }
+
+    #[test]
+    fn cancelled_expansion_fails() {
+        let tiling = presets::load_preset("socolar_5").unwrap();
+        let progress = Progress::default();
+        progress.cancel();
+        let options = LevelOptions {
+            max_tiles: None,
+            parallel: false,
+            progress: &progress,
+        };
+        let result = tiling.expand_visible(&seed(), 3, None, &options);
+        assert!(matches!(result, Err(TilingError::Cancelled)));
+    }
+}
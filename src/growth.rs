@@ -0,0 +1,595 @@
+use std::collections::HashMap;
+
+use kurbo::{Affine, Point, Rect, Shape, Vec2};
+
+use crate::progress::Progress;
+use crate::tiling::*;
+use crate::tiling_validation::polygon_contains;
+
+/// Tile corners closer than this, relative to the tile scale, are the same vertex.
+const VERTEX_RESOLUTION: f64 = 1e-6;
+/// Relative tolerance for edge lengths and orientations of glued tiles.
+const FIT_TOLERANCE: f64 = 1e-6;
+/// Open edges nearest to the seed that are checked for forced moves before every placement.
+const LOOKAHEAD: usize = 8;
+/// How far overlap probes lie inside a tile, relative to the edge length.
+const PROBE_OFFSET: f64 = 1e-3;
+
+/// Grows a patch tile by tile from the edge labels of the prototiles instead of substituting,
+/// see `TilingStep::grow`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GrowthSettings {
+    /// Dead ends undone before giving up on covering the bounds.
+    pub max_backtracks: usize,
+    /// Tiles may be rotated, off keeps every tile in the orientation of the first seed tile,
+    /// as needed for Wang tiles.
+    pub rotations: bool,
+    pub reflections: bool,
+    /// Shuffles the order in which candidates are tried.
+    pub seed: u64,
+}
+
+impl Default for GrowthSettings {
+    fn default() -> Self {
+        Self {
+            max_backtracks: 10000,
+            rotations: true,
+            reflections: false,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GrowthResult {
+    pub tiles: usize,
+    pub backtracks: usize,
+    /// Every edge inside the bounds has a neighbour.
+    pub complete: bool,
+    /// Stopped at the tile limit.
+    pub truncated: bool,
+}
+
+type VertexKey = (i64, i64);
+type EdgeKey = (VertexKey, VertexKey);
+
+struct PlacedTile {
+    outline: Vec<Point>,
+    bounds: Rect,
+    /// Grid cells the tile was added to.
+    cells: Vec<(i64, i64)>,
+}
+
+#[derive(Clone, Copy)]
+struct OpenEdge {
+    tile: usize,
+    edge: usize,
+    from: Point,
+    to: Point,
+}
+
+/// Changes to the open edges made by one placement, so that it can be undone.
+struct PlacementLog {
+    closed: Vec<(EdgeKey, OpenEdge)>,
+    opened: Vec<EdgeKey>,
+}
+
+struct Decision {
+    candidates: Vec<TilePlacement>,
+    next: usize,
+    tiles: usize,
+}
+
+enum NextMove {
+    Done,
+    DeadEnd,
+    Candidates(Vec<TilePlacement>),
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+fn shuffle<T>(items: &mut [T], state: &mut u64) {
+    for i in (1..items.len()).rev() {
+        let j = (splitmix64(state) % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
+
+fn cross(o: Point, a: Point, b: Point) -> f64 {
+    (a - o).cross(b - o)
+}
+
+/// Segments crossing at a point inside both, touching or collinear segments don't count.
+fn segments_cross(a: Point, b: Point, c: Point, d: Point, tolerance: f64) -> bool {
+    let d1 = cross(c, d, a);
+    let d2 = cross(c, d, b);
+    let d3 = cross(a, b, c);
+    let d4 = cross(a, b, d);
+    let tolerance = tolerance * (b - a).hypot() * (d - c).hypot();
+    let opposite =
+        |p: f64, q: f64| (p > tolerance && q < -tolerance) || (p < -tolerance && q > tolerance);
+    opposite(d1, d2) && opposite(d3, d4)
+}
+
+/// Points just inside the middle of every edge.
+fn probes(polygon: &[Point]) -> Vec<Point> {
+    let n = polygon.len();
+    let twice_area: f64 = (0..n)
+        .map(|i| polygon[i].to_vec2().cross(polygon[(i + 1) % n].to_vec2()))
+        .sum();
+    let winding = twice_area.signum();
+    (0..n)
+        .map(|i| {
+            let (a, b) = (polygon[i], polygon[(i + 1) % n]);
+            let d = b - a;
+            a.midpoint(b) + Vec2::new(-d.y, d.x) * (winding * PROBE_OFFSET)
+        })
+        .collect()
+}
+
+/// Whether the interiors of two simple polygons overlap, tiles sharing edges or corners don't.
+fn interiors_overlap(a: &[Point], b: &[Point]) -> bool {
+    for i in 0..a.len() {
+        for j in 0..b.len() {
+            let (a0, a1) = (a[i], a[(i + 1) % a.len()]);
+            let (b0, b1) = (b[j], b[(j + 1) % b.len()]);
+            if segments_cross(a0, a1, b0, b1, FIT_TOLERANCE) {
+                return true;
+            }
+        }
+    }
+    probes(a).into_iter().any(|p| polygon_contains(b, p))
+        || probes(b).into_iter().any(|p| polygon_contains(a, p))
+}
+
+fn same_linear(a: &Affine, b: &Affine, tolerance: f64) -> bool {
+    let (a, b) = (a.as_coeffs(), b.as_coeffs());
+    (0..4).all(|i| (a[i] - b[i]).abs() <= tolerance)
+}
+
+struct Patch<'a> {
+    tiling: &'a TilingStep,
+    settings: &'a GrowthSettings,
+    /// Linear part of the first seed tile, kept by every tile when rotations are off.
+    orientation: Affine,
+    scale: f64,
+    cell_size: f64,
+    placements: Vec<TilePlacement>,
+    tiles: Vec<PlacedTile>,
+    open: HashMap<EdgeKey, OpenEdge>,
+    grid: HashMap<(i64, i64), Vec<usize>>,
+    log: Vec<PlacementLog>,
+}
+
+impl<'a> Patch<'a> {
+    fn new(
+        tiling: &'a TilingStep,
+        settings: &'a GrowthSettings,
+        first: &TilePlacement,
+    ) -> Patch<'a> {
+        let scale = first.transform.determinant().abs().sqrt();
+        let size = tiling
+            .rules
+            .iter()
+            .map(|rule| {
+                let bounds = rule.tile.to_path().control_box();
+                bounds.width().max(bounds.height())
+            })
+            .fold(0.0, f64::max);
+        let [a, b, c, d, _, _] = first.transform.as_coeffs();
+        Patch {
+            tiling,
+            settings,
+            orientation: Affine::new([a, b, c, d, 0.0, 0.0]),
+            scale,
+            cell_size: (size * scale).max(f64::MIN_POSITIVE),
+            placements: Vec::new(),
+            tiles: Vec::new(),
+            open: HashMap::new(),
+            grid: HashMap::new(),
+            log: Vec::new(),
+        }
+    }
+
+    fn vertex_key(&self, p: Point) -> VertexKey {
+        let quantum = VERTEX_RESOLUTION * self.scale;
+        (
+            (p.x / quantum).round() as i64,
+            (p.y / quantum).round() as i64,
+        )
+    }
+
+    fn edge_key(&self, a: Point, b: Point) -> EdgeKey {
+        let (a, b) = (self.vertex_key(a), self.vertex_key(b));
+        (a.min(b), a.max(b))
+    }
+
+    fn cells(&self, bounds: Rect) -> Vec<(i64, i64)> {
+        let cell = |v: f64| (v / self.cell_size).floor() as i64;
+        let mut result = Vec::new();
+        for x in cell(bounds.x0)..=cell(bounds.x1) {
+            for y in cell(bounds.y0)..=cell(bounds.y1) {
+                result.push((x, y));
+            }
+        }
+        return result;
+    }
+
+    fn edges(&self, placement: &TilePlacement) -> Vec<(usize, Point, Point)> {
+        let corners = &self.tiling.rules[placement.tile_id].tile.corners;
+        let n = corners.len();
+        (0..n)
+            .map(|k| {
+                let a = placement.transform * corners[k];
+                (k, a, placement.transform * corners[(k + 1) % n])
+            })
+            .collect()
+    }
+
+    fn label(&self, tile: usize, edge: usize) -> EdgeLabel {
+        self.tiling.rules[self.placements[tile].tile_id]
+            .tile
+            .label(edge)
+    }
+
+    /// Checks the labels against every edge the placement would close and that it doesn't
+    /// overlap any placed tile.
+    fn fits(&self, placement: &TilePlacement) -> bool {
+        let tile = &self.tiling.rules[placement.tile_id].tile;
+        for (k, a, b) in self.edges(placement) {
+            if let Some(open) = self.open.get(&self.edge_key(a, b)) {
+                let same_direction = self.vertex_key(a) == self.vertex_key(open.from);
+                if !tile
+                    .label(k)
+                    .fits(&self.label(open.tile, open.edge), same_direction)
+                {
+                    return false;
+                }
+            }
+        }
+        let outline: Vec<Point> = tile
+            .outline_points()
+            .into_iter()
+            .map(|p| placement.transform * p)
+            .collect();
+        let bounds = Rect::from_points(outline[0], outline[0]);
+        let bounds = outline.iter().fold(bounds, |bounds, p| bounds.union_pt(*p));
+        for cell in self.cells(bounds) {
+            for other in self.grid.get(&cell).into_iter().flatten() {
+                let other = &self.tiles[*other];
+                if other.bounds.intersect(bounds).area() > 0.0
+                    && interiors_overlap(&outline, &other.outline)
+                {
+                    return false;
+                }
+            }
+        }
+        return true;
+    }
+
+    fn add(&mut self, placement: TilePlacement) {
+        let index = self.placements.len();
+        let tile = &self.tiling.rules[placement.tile_id].tile;
+        let outline: Vec<Point> = tile
+            .outline_points()
+            .into_iter()
+            .map(|p| placement.transform * p)
+            .collect();
+        let bounds = outline
+            .iter()
+            .fold(Rect::from_points(outline[0], outline[0]), |bounds, p| {
+                bounds.union_pt(*p)
+            });
+        let cells = self.cells(bounds);
+        for cell in &cells {
+            self.grid.entry(*cell).or_default().push(index);
+        }
+        let mut log = PlacementLog {
+            closed: Vec::new(),
+            opened: Vec::new(),
+        };
+        for (edge, from, to) in self.edges(&placement) {
+            let key = self.edge_key(from, to);
+            match self.open.remove(&key) {
+                Some(open) => log.closed.push((key, open)),
+                None => {
+                    let open = OpenEdge {
+                        tile: index,
+                        edge,
+                        from,
+                        to,
+                    };
+                    self.open.insert(key, open);
+                    log.opened.push(key);
+                }
+            }
+        }
+        self.placements.push(placement);
+        self.tiles.push(PlacedTile {
+            outline,
+            bounds,
+            cells,
+        });
+        self.log.push(log);
+    }
+
+    fn undo(&mut self) {
+        let (Some(tile), Some(log)) = (self.tiles.pop(), self.log.pop()) else {
+            return;
+        };
+        let index = self.tiles.len();
+        self.placements.pop();
+        for cell in &tile.cells {
+            if let Some(list) = self.grid.get_mut(cell) {
+                if list.last() == Some(&index) {
+                    list.pop();
+                }
+            }
+        }
+        for key in log.opened {
+            self.open.remove(&key);
+        }
+        for (key, open) in log.closed {
+            self.open.insert(key, open);
+        }
+    }
+
+    /// Every prototile placement that can be glued onto `open` from the other side.
+    fn candidates(&self, open: &OpenEdge) -> Vec<TilePlacement> {
+        let open_label = self.label(open.tile, open.edge);
+        let length = (open.to - open.from).hypot();
+        let mut result = Vec::new();
+        for (tile_id, rule) in self.tiling.rules.iter().enumerate() {
+            let corners = &rule.tile.corners;
+            let n = corners.len();
+            for k in 0..n {
+                let (c0, c1) = (corners[k], corners[(k + 1) % n]);
+                let ratio = length / (c1 - c0).hypot();
+                if (ratio - self.scale).abs() > FIT_TOLERANCE * self.scale {
+                    continue;
+                }
+                let inverse = EdgeShape::edge_frame(c0, c1).inverse();
+                let label = rule.tile.label(k);
+                let mut transforms = Vec::new();
+                // Same winding: the new tile runs along the edge the other way.
+                if label.fits(&open_label, false) {
+                    transforms.push(EdgeShape::edge_frame(open.to, open.from) * inverse);
+                }
+                if self.settings.reflections && label.fits(&open_label, true) {
+                    let frame = EdgeShape::edge_frame(open.from, open.to);
+                    transforms.push(frame * Affine::FLIP_Y * inverse);
+                }
+                for transform in transforms {
+                    if !self.settings.rotations
+                        && !same_linear(&transform, &self.orientation, FIT_TOLERANCE * self.scale)
+                    {
+                        continue;
+                    }
+                    let placement = TilePlacement { tile_id, transform };
+                    if self.fits(&placement) {
+                        result.push(placement);
+                    }
+                }
+            }
+        }
+        return result;
+    }
+
+    /// Candidates of the most constrained open edge among those nearest to the seed, so forced
+    /// moves are made first and dead ends show up early.
+    fn next_move(&self, bounds: Rect, center: Point, rng: &mut u64) -> NextMove {
+        let mut open: Vec<&OpenEdge> = self
+            .open
+            .values()
+            .filter(|edge| bounds.contains(edge.from.midpoint(edge.to)))
+            .collect();
+        if open.is_empty() {
+            return NextMove::Done;
+        }
+        let distance = |edge: &OpenEdge| (edge.from.midpoint(edge.to) - center).hypot2();
+        open.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+        let mut best: Option<Vec<TilePlacement>> = None;
+        for edge in open.into_iter().take(LOOKAHEAD) {
+            let candidates = self.candidates(edge);
+            if candidates.is_empty() {
+                return NextMove::DeadEnd;
+            }
+            if best
+                .as_ref()
+                .map_or(true, |best| candidates.len() < best.len())
+            {
+                best = Some(candidates);
+            }
+        }
+        let mut candidates = best.unwrap_or_default();
+        shuffle(&mut candidates, rng);
+        return NextMove::Candidates(candidates);
+    }
+}
+
+impl TilingStep {
+    /// Grows a patch from `seed` by gluing prototiles edge to edge wherever their labels fit,
+    /// until every edge inside `bounds` has a neighbour. Dead ends are undone by backtracking.
+    /// Only edges between the same pair of corners are matched, so the prototiles should have
+    /// edges of equal length.
+    pub fn grow(
+        &self,
+        seed: &[TilePlacement],
+        bounds: Rect,
+        max_tiles: usize,
+        settings: &GrowthSettings,
+        progress: &Progress,
+    ) -> Result<(Vec<TilePlacement>, GrowthResult), TilingError> {
+        self.validate()?;
+        self.validate_placements(seed)?;
+        let mut result = GrowthResult::default();
+        let Some(first) = seed.first() else {
+            return Ok((Vec::new(), result));
+        };
+        let mut patch = Patch::new(self, settings, first);
+        for placement in seed {
+            patch.add(placement.clone());
+        }
+        let center = first.transform * Point::ZERO;
+        let mut rng = settings.seed;
+        let mut decisions: Vec<Decision> = Vec::new();
+        loop {
+            progress.check()?;
+            progress.set_tiles(patch.placements.len());
+            if patch.placements.len() >= max_tiles {
+                result.truncated = true;
+                break;
+            }
+            let candidates = match patch.next_move(bounds, center, &mut rng) {
+                NextMove::Done => {
+                    result.complete = true;
+                    break;
+                }
+                NextMove::Candidates(candidates) => candidates,
+                NextMove::DeadEnd => Vec::new(),
+            };
+            if let Some(first) = candidates.first() {
+                patch.add(first.clone());
+                decisions.push(Decision {
+                    candidates,
+                    next: 1,
+                    tiles: patch.placements.len() - 1,
+                });
+                continue;
+            }
+            if result.backtracks >= settings.max_backtracks {
+                break;
+            }
+            result.backtracks += 1;
+            // Undo up to the last decision that has untried candidates.
+            while let Some(decision) = decisions.last_mut() {
+                while patch.placements.len() > decision.tiles {
+                    patch.undo();
+                }
+                if let Some(next) = decision.candidates.get(decision.next) {
+                    patch.add(next.clone());
+                    decision.next += 1;
+                    break;
+                }
+                decisions.pop();
+            }
+            if decisions.is_empty() {
+                // No tiling of the bounds exists around this seed.
+                break;
+            }
+        }
+        result.tiles = patch.placements.len();
+        return Ok((patch.placements, result));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::presets;
+
+    fn seed(tile_id: usize) -> Vec<TilePlacement> {
+        vec![TilePlacement {
+            tile_id,
+            transform: Affine::IDENTITY,
+        }]
+    }
+
+    /// Unit squares with the given label kinds on their left, top, right and bottom edges.
+    fn wang_tiles(kinds: &[[u32; 4]]) -> TilingStep {
+        let mut tiling = presets::load_preset("square_grid").unwrap();
+        let square = tiling.rules[0].clone();
+        tiling.rules = kinds
+            .iter()
+            .map(|kinds| {
+                let mut rule = square.clone();
+                for (edge, kind) in kinds.iter().enumerate() {
+                    rule.tile.label_mut(edge).kind = *kind;
+                }
+                rule
+            })
+            .collect();
+        return tiling;
+    }
+
+    /// Grid cell of a unit square placement in any orientation.
+    fn cell(tile: &TilePlacement) -> (i64, i64) {
+        let center = tile.transform * Point::new(0.5, 0.5);
+        (center.x.floor() as i64, center.y.floor() as i64)
+    }
+
+    #[test]
+    fn square_grid_grows_to_completion() {
+        let tiling = presets::load_preset("square_grid").unwrap();
+        let bounds = Rect::new(-2.0, -2.0, 2.0, 2.0);
+        let settings = GrowthSettings::default();
+        let (tiles, result) = tiling
+            .grow(&seed(0), bounds, 1000, &settings, &Progress::default())
+            .unwrap();
+        assert!(result.complete);
+        assert!(!result.truncated);
+        assert_eq!(result.backtracks, 0);
+        let cells: HashSet<(i64, i64)> = tiles.iter().map(cell).collect();
+        assert_eq!(cells.len(), tiles.len());
+        for x in -2..2 {
+            for y in -2..2 {
+                assert!(cells.contains(&(x, y)), "cell ({x}, {y}) is empty");
+            }
+        }
+    }
+
+    #[test]
+    fn wang_tiles_keep_their_labels() {
+        // Only a checkerboard fits, a half turn of either tile would carry the other's labels.
+        let tiling = wang_tiles(&[[1, 6, 2, 5], [2, 5, 1, 6]]);
+        let settings = GrowthSettings {
+            rotations: false,
+            ..Default::default()
+        };
+        let bounds = Rect::new(-2.0, -2.0, 2.0, 2.0);
+        let (tiles, result) = tiling
+            .grow(&seed(0), bounds, 1000, &settings, &Progress::default())
+            .unwrap();
+        assert!(result.complete);
+        for tile in &tiles {
+            let identity = Affine::IDENTITY;
+            assert!(same_linear(&tile.transform, &identity, FIT_TOLERANCE));
+            let (x, y) = cell(tile);
+            let tile_id = (x + y).rem_euclid(2) as usize;
+            assert_eq!(tile.tile_id, tile_id, "cell ({x}, {y})");
+        }
+    }
+
+    #[test]
+    fn impossible_labels_give_up() {
+        // Every bottom edge is constrained and no bottom fits the last top, so the columns end
+        // two tiles above the seed whatever is placed beside them.
+        let tiling = wang_tiles(&[
+            [0, 2, 0, 1],
+            [0, 1, 0, 1],
+            [0, 3, 0, 2],
+            [0, 3, 0, 2],
+            [0, 9, 0, 3],
+        ]);
+        let settings = GrowthSettings {
+            max_backtracks: 20,
+            rotations: false,
+            ..Default::default()
+        };
+        let bounds = Rect::new(-3.0, -3.0, 4.0, 4.0);
+        let (_, result) = tiling
+            .grow(&seed(0), bounds, 1000, &settings, &Progress::default())
+            .unwrap();
+        assert!(!result.complete);
+        assert!(!result.truncated);
+        assert!(result.backtracks <= settings.max_backtracks);
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::BTreeMap;
+
+use kurbo::{Affine, BezPath};
+
+use crate::progress::Progress;
+use crate::render::{finish_path, RenderSettings};
+use crate::tiling::*;
+
+/// Colours for output layers that don't have one configured in the rules.
+const PALETTE: [[u8; 3]; 8] = [
+    [0, 0, 0],
+    [220, 40, 40],
+    [30, 90, 220],
+    [20, 150, 60],
+    [230, 140, 0],
+    [140, 50, 180],
+    [0, 160, 170],
+    [120, 80, 40],
+];
+/// Rotations closer than this, in degrees, belong to the same orientation class.
+const ORIENTATION_RESOLUTION: f64 = 1e-3;
+
+/// Name and pen colour of an output layer, referenced by `TilingRule::layer`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputLayer {
+    pub name: String,
+    pub color: [u8; 3],
+}
+
+impl OutputLayer {
+    pub fn new(index: usize) -> OutputLayer {
+        OutputLayer {
+            name: format!("layer {index}"),
+            color: PALETTE[index % PALETTE.len()],
+        }
+    }
+}
+
+impl TilingStep {
+    pub fn output_layer(&self, index: usize) -> OutputLayer {
+        match self.layers.get(index) {
+            Some(layer) => layer.clone(),
+            None => OutputLayer::new(index),
+        }
+    }
+
+    /// Adds default layers up to `index` if needed.
+    pub fn output_layer_mut(&mut self, index: usize) -> &mut OutputLayer {
+        while self.layers.len() <= index {
+            self.layers.push(OutputLayer::new(self.layers.len()));
+        }
+        &mut self.layers[index]
+    }
+}
+
+/// What the tiles are grouped by when splitting the output into layers. With everything off all
+/// tiles end up in a single layer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LayerSplit {
+    /// `TilingRule::layer` of the tile.
+    pub prototile: bool,
+    /// Rotation and reflection of the tile.
+    pub orientation: bool,
+    /// Prototile of the parent tile, needs the hierarchy addresses from the expansion.
+    pub parent: bool,
+}
+
+impl LayerSplit {
+    pub fn is_split(&self) -> bool {
+        self.prototile || self.orientation || self.parent
+    }
+}
+
+/// Orientation of a placement: whether it is mirrored and the rotation in units of
+/// `ORIENTATION_RESOLUTION` degrees.
+pub fn orientation_class(transform: &Affine) -> (bool, i64) {
+    let [a, b, c, d, _, _] = transform.as_coeffs();
+    let mirrored = a * d - b * c < 0.0;
+    let degrees = b.atan2(a).to_degrees().rem_euclid(360.0);
+    let steps = (360.0 / ORIENTATION_RESOLUTION).round() as i64;
+    let class = (degrees / ORIENTATION_RESOLUTION).round() as i64 % steps;
+    (mirrored, class)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct LayerKey {
+    layer: Option<usize>,
+    orientation: Option<(bool, i64)>,
+    parent: Option<usize>,
+    color: Option<usize>,
+}
+
+#[derive(Clone)]
+pub struct LayerPath {
+    /// Whiskers layer id, consecutive starting from 1 when split, 0 otherwise.
+    pub layer: usize,
+    pub name: String,
+    /// `None` keeps the default pen.
+    pub color: Option<[u8; 3]>,
+    /// In page coordinates, see `RenderSettings::page_transform`.
+    pub path: BezPath,
+}
+
+/// Sorts tiles into one path per layer. Tile decorations, when enabled, go on a layer of their
+/// own after the tile layers.
+pub struct LayerBuilder<'a> {
+    tiling: &'a TilingStep,
+    split: LayerSplit,
+    paths: BTreeMap<LayerKey, BezPath>,
+    decorations: Option<BezPath>,
+}
+
+impl<'a> LayerBuilder<'a> {
+    pub fn new(tiling: &'a TilingStep, split: LayerSplit, decorations: bool) -> LayerBuilder<'a> {
+        LayerBuilder {
+            tiling,
+            split,
+            paths: BTreeMap::new(),
+            decorations: decorations.then(BezPath::new),
+        }
+    }
+
+    /// `parent` is the tile id of the tile's parent, only used when splitting by parent. Tiles
+    /// with a colour from `coloring::color_tiles` get a layer per colour on top of the split.
+    pub fn add(&mut self, tile: &TilePlacement, parent: Option<usize>, color: Option<usize>) {
+        let Some(rule) = self.tiling.rules.get(tile.tile_id) else {
+            return;
+        };
+        let key = LayerKey {
+            layer: Some(rule.layer).filter(|_| self.split.prototile),
+            orientation: Some(orientation_class(&tile.transform))
+                .filter(|_| self.split.orientation),
+            parent: parent.filter(|_| self.split.parent),
+            color,
+        };
+        let path = self.paths.entry(key).or_insert_with(BezPath::new);
+        rule.tile.add_to_path_t(path, &tile.transform);
+        if let Some(decorations) = &mut self.decorations {
+            rule.tile.add_decorations_t(decorations, &tile.transform);
+        }
+    }
+
+    fn name(&self, key: &LayerKey) -> String {
+        let mut parts = Vec::new();
+        if let Some(layer) = key.layer {
+            parts.push(self.tiling.output_layer(layer).name);
+        }
+        if let Some((mirrored, class)) = key.orientation {
+            let degrees = class as f64 * ORIENTATION_RESOLUTION;
+            let mirrored = if mirrored { " mirrored" } else { "" };
+            parts.push(format!("{degrees:.1}°{mirrored}"));
+        }
+        if let Some(parent) = key.parent {
+            match self.tiling.rules.get(parent) {
+                Some(rule) if !rule.name.is_empty() => parts.push(format!("in {}", rule.name)),
+                _ => parts.push(format!("in {parent}")),
+            }
+        }
+        if let Some(color) = key.color {
+            parts.push(format!("colour {color}"));
+        }
+        return parts.join(" ");
+    }
+
+    /// Moves every layer to the page and applies clipping and the plotter optimizations.
+    pub fn finish(
+        self,
+        settings: &RenderSettings,
+        progress: &Progress,
+    ) -> Result<Vec<LayerPath>, TilingError> {
+        let colored = self.paths.keys().any(|key| key.color.is_some());
+        let mut result: Vec<LayerPath> = if !self.split.is_split() && !colored {
+            let path = self.paths.into_values().next().unwrap_or_default();
+            vec![LayerPath {
+                layer: 0,
+                name: String::new(),
+                color: None,
+                path: finish_path(path, settings, progress)?,
+            }]
+        } else {
+            let names: Vec<String> = self.paths.keys().map(|key| self.name(key)).collect();
+            let tiling = self.tiling;
+            self.paths
+                .into_iter()
+                .zip(names)
+                .enumerate()
+                .map(|(i, ((key, path), name))| {
+                    let color = match (key.color, key.layer) {
+                        (Some(color), _) => PALETTE[color % PALETTE.len()],
+                        (None, Some(layer)) => tiling.output_layer(layer).color,
+                        (None, None) => PALETTE[i % PALETTE.len()],
+                    };
+                    Ok(LayerPath {
+                        layer: i + 1,
+                        name,
+                        color: Some(color),
+                        path: finish_path(path, settings, progress)?,
+                    })
+                })
+                .collect::<Result<_, TilingError>>()?
+        };
+        if let Some(decorations) = self.decorations.filter(|path| !path.elements().is_empty()) {
+            let layer = result
+                .iter()
+                .map(|layer| layer.layer + 1)
+                .max()
+                .unwrap_or(0);
+            result.push(LayerPath {
+                layer,
+                name: String::from("decorations"),
+                color: None,
+                path: finish_path(decorations, settings, progress)?,
+            });
+        }
+        return Ok(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kurbo::{Line, PathEl, Shape};
+
+    use super::*;
+    use crate::presets;
+
+    fn patch(tiling: &TilingStep) -> Vec<TilePlacement> {
+        let seed = vec![TilePlacement {
+            tile_id: 0,
+            transform: Affine::IDENTITY,
+        }];
+        let mut tiles = Vec::new();
+        tiling.expand_levels(&seed, 3, &mut tiles, None);
+        return tiles;
+    }
+
+    /// Layers of `tiles` with default render settings, tile i having parent `i % 2`.
+    fn build(
+        tiling: &TilingStep,
+        tiles: &[TilePlacement],
+        split: LayerSplit,
+        decorations: bool,
+    ) -> Vec<LayerPath> {
+        let mut builder = LayerBuilder::new(tiling, split, decorations);
+        for (i, tile) in tiles.iter().enumerate() {
+            builder.add(tile, Some(i % 2), None);
+        }
+        let settings = RenderSettings::default();
+        return builder.finish(&settings, &Progress::default()).unwrap();
+    }
+
+    fn tile_count(layer: &LayerPath) -> usize {
+        let moves = layer.path.elements().iter();
+        return moves.filter(|el| matches!(el, PathEl::MoveTo(_))).count();
+    }
+
+    /// Tile count of every layer, sorted.
+    fn counts(layers: &[LayerPath]) -> Vec<usize> {
+        let mut result: Vec<usize> = layers.iter().map(tile_count).collect();
+        result.sort();
+        return result;
+    }
+
+    fn sorted_group_sizes<K: Ord>(keys: impl Iterator<Item = K>) -> Vec<usize> {
+        let mut groups: BTreeMap<K, usize> = BTreeMap::new();
+        for key in keys {
+            *groups.entry(key).or_default() += 1;
+        }
+        let mut result: Vec<usize> = groups.into_values().collect();
+        result.sort();
+        return result;
+    }
+
+    #[test]
+    fn unsplit_output_is_one_layer() {
+        let tiling = presets::load_preset("socolar_5").unwrap();
+        let tiles = patch(&tiling);
+        let layers = build(&tiling, &tiles, LayerSplit::default(), false);
+        assert_eq!(layers.len(), 1);
+        assert_eq!(layers[0].layer, 0);
+        assert_eq!(tile_count(&layers[0]), tiles.len());
+    }
+
+    #[test]
+    fn splits_put_every_tile_in_one_layer() {
+        let mut tiling = presets::load_preset("socolar_5").unwrap();
+        tiling.rules[1].layer = 1;
+        tiling.output_layer_mut(1).name = String::from("thin");
+        let tiles = patch(&tiling);
+
+        let prototile = LayerSplit {
+            prototile: true,
+            ..Default::default()
+        };
+        let layers = build(&tiling, &tiles, prototile, false);
+        let names: Vec<&str> = layers.iter().map(|layer| layer.name.as_str()).collect();
+        assert_eq!(names, ["layer 0", "thin"]);
+        let thin = tiles.iter().filter(|tile| tile.tile_id == 1).count();
+        assert_eq!(tile_count(&layers[1]), thin);
+        assert_eq!(tile_count(&layers[0]), tiles.len() - thin);
+
+        let orientation = LayerSplit {
+            orientation: true,
+            ..Default::default()
+        };
+        let layers = build(&tiling, &tiles, orientation, false);
+        let classes = tiles.iter().map(|tile| orientation_class(&tile.transform));
+        assert!(layers.len() > 2);
+        assert_eq!(counts(&layers), sorted_group_sizes(classes));
+
+        let parent = LayerSplit {
+            parent: true,
+            ..Default::default()
+        };
+        let layers = build(&tiling, &tiles, parent, false);
+        let names: Vec<&str> = layers.iter().map(|layer| layer.name.as_str()).collect();
+        assert_eq!(names, ["in fat rhombus", "in thin rhombus"]);
+        let parents = (0..tiles.len()).map(|i| i % 2);
+        assert_eq!(counts(&layers), sorted_group_sizes(parents));
+
+        let all = LayerSplit {
+            prototile: true,
+            orientation: true,
+            parent: true,
+        };
+        let layers = build(&tiling, &tiles, all, false);
+        assert_eq!(layers.iter().map(tile_count).sum::<usize>(), tiles.len());
+        let ids: Vec<usize> = layers.iter().map(|layer| layer.layer).collect();
+        assert_eq!(ids, (1..=layers.len()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn decorations_get_their_own_layer() {
+        let mut tiling = presets::load_preset("socolar_5").unwrap();
+        let bar = Line::new((0.0, 0.0), (0.5, 0.0)).into_path(0.1);
+        tiling.rules[0].tile.decorations.push(bar);
+        let tiles = patch(&tiling);
+        let fat = tiles.iter().filter(|tile| tile.tile_id == 0).count();
+
+        let layers = build(&tiling, &tiles, LayerSplit::default(), true);
+        assert_eq!(layers.len(), 2);
+        assert_eq!(tile_count(&layers[0]), tiles.len());
+        assert_eq!(layers[1].layer, 1);
+        assert_eq!(layers[1].name, "decorations");
+        assert_eq!(tile_count(&layers[1]), fat);
+
+        let prototile = LayerSplit {
+            prototile: true,
+            ..Default::default()
+        };
+        let layers = build(&tiling, &tiles, prototile, true);
+        let last = layers.last().unwrap();
+        assert_eq!(last.layer, layers.len());
+        assert_eq!(last.name, "decorations");
+        assert_eq!(tile_count(last), fat);
+
+        let layers = build(&tiling, &tiles, LayerSplit::default(), false);
+        assert_eq!(layers.len(), 1);
+    }
+}
@@ -1,11 +1,43 @@
 use std::{time::Instant, vec};
 
-use kurbo::{Affine, Point, Rect, Vec2};
+use background::{BackgroundGenerator, JobState};
+use clip::{ClipMask, ClipMode};
+use coloring::ColoringScheme;
+use growth::GrowthSettings;
+use layers::LayerSplit;
+use multigrid::{parse_offsets, MultigridSettings};
+use render::*;
+use status_ui::ExpansionStatus;
 use tiling::*;
 use whiskers::prelude::*;
 
+mod address;
+mod background;
+mod clip;
+mod coloring;
+mod coloring_ui;
+mod culling;
+mod depth_first;
+mod edge_matching;
+mod edges;
+mod expansion;
+mod growth;
+mod layers;
+mod matching_rules;
+mod multigrid;
+mod plot_optimize;
+mod presets;
+mod progress;
+mod render;
+mod render_cli;
+mod status_ui;
+mod supertile_ui;
+mod tile_cache;
 mod tiling;
+mod tiling_analysis;
 mod tiling_editor_ui;
+mod tiling_file;
+mod tiling_validation;
 
 #[derive(Sketch)]
 struct TilingSketch {
@@ -19,12 +51,60 @@ struct TilingSketch {
     initial_scale: f64,
     fixed_size_max_level: bool,
     levels: usize,
+    polygon_limit: usize,
+    depth_first: bool,
+    parallel: bool,
+    /// Falls back to the default seed tile when the tiling has no such prototile.
+    seed_tile: usize,
+    seed_patch: bool,
+    #[param(slider, min = -180.0, max = 180.0)]
+    seed_rotation: f64,
+    seed_position: whiskers::prelude::Point,
+    /// Grow the patch tile by tile from the edge labels of the prototiles instead of
+    /// substituting, tiles have the size of the initial scale.
+    grow_from_labels: bool,
+    grow_backtracks: usize,
+    /// Off keeps every tile in the seed orientation, as for Wang tiles.
+    grow_rotations: bool,
+    grow_reflections: bool,
+    grow_seed: u32,
+    /// Rhombus tiling from de Bruijn's multigrid instead of substituting, drawn with the rhombus
+    /// prototiles at the size of the initial scale.
+    multigrid: bool,
+    /// 5 for Penrose, 8 for Ammann-Beenker.
+    multigrid_symmetry: usize,
+    /// Offsets of the grids separated by commas, grids without one get generic offsets.
+    multigrid_offsets: String,
+    merge_shared_edges: bool,
+    #[param(slider, min = 0.0001, max = 0.1)]
+    merge_tolerance: f64,
+    optimize_pen_travel: bool,
+    clip_to_mask: bool,
+    /// Drop tiles crossing the mask border instead of cutting them.
+    whole_tiles_only: bool,
+    circle_mask: bool,
+    /// SVG path data in page mm, overrides the frame and circle masks when set.
+    mask_path: String,
+    /// Outlines of larger tiles, each level on its own layer.
+    supertiles: SupertileLayers,
+    /// Put each output layer of the rules on its own layer, colours are set in the tiling editor.
+    layers_by_prototile: bool,
+    layers_by_orientation: bool,
+    layers_by_parent: bool,
+    /// Puts each colour on its own layer as well.
+    coloring: ColoringScheme,
+    /// Decorations of the prototiles, drawn on a layer after the tiles.
+    draw_decorations: bool,
 
     tiling: TilingStep,
+    status: ExpansionStatus,
+    #[skip]
+    generator: BackgroundGenerator,
 }
 
 impl Default for TilingSketch {
     fn default() -> Self {
+        let tiling = TilingStep::new();
         Self {
             width: 100.0,
             height: 100.0,
@@ -32,183 +112,187 @@ impl Default for TilingSketch {
             line_thickness: 0.5,
             initial_scale: 1.0,
             fixed_size_max_level: false,
-            tiling: TilingStep::new(),
+            seed_tile: tiling.default_seed_tile(),
+            tiling,
             levels: 5,
+            polygon_limit: DEFAULT_POLYGON_LIMIT,
+            depth_first: false,
+            parallel: true,
+            seed_patch: false,
+            seed_rotation: 0.0,
+            seed_position: whiskers::prelude::Point::new(0.0, 0.0),
+            grow_from_labels: false,
+            grow_backtracks: 10000,
+            grow_rotations: true,
+            grow_reflections: false,
+            grow_seed: 0,
+            multigrid: false,
+            multigrid_symmetry: 5,
+            multigrid_offsets: String::new(),
+            merge_shared_edges: false,
+            merge_tolerance: 0.001,
+            optimize_pen_travel: false,
+            clip_to_mask: false,
+            whole_tiles_only: false,
+            circle_mask: false,
+            mask_path: String::new(),
+            supertiles: SupertileLayers::default(),
+            layers_by_prototile: false,
+            layers_by_orientation: false,
+            layers_by_parent: false,
+            coloring: ColoringScheme::None,
+            draw_decorations: true,
+            status: ExpansionStatus::default(),
+            generator: BackgroundGenerator::default(),
         }
     }
 }
 
+impl TilingSketch {
+    fn render_settings(&self) -> std::result::Result<RenderSettings, Box<dyn std::error::Error>> {
+        let mask = if !self.mask_path.trim().is_empty() {
+            MaskShape::Custom(ClipMask::from_svg(&self.mask_path)?)
+        } else if self.circle_mask {
+            MaskShape::Circle
+        } else {
+            MaskShape::Frame
+        };
+        let clip = match (self.clip_to_mask, self.whole_tiles_only) {
+            (false, _) => None,
+            (true, false) => Some(ClipMode::Trim),
+            (true, true) => Some(ClipMode::WholeTiles),
+        };
+        let multigrid = if self.multigrid {
+            Some(MultigridSettings {
+                symmetry: self.multigrid_symmetry,
+                offsets: parse_offsets(&self.multigrid_offsets)?,
+            })
+        } else {
+            None
+        };
+        Ok(RenderSettings {
+            width: self.width,
+            height: self.height,
+            offset: self.offset.into(),
+            line_thickness: self.line_thickness,
+            initial_scale: self.initial_scale,
+            fixed_size_max_level: self.fixed_size_max_level,
+            levels: self.levels,
+            polygon_limit: self.polygon_limit,
+            depth_first: self.depth_first,
+            parallel: self.parallel,
+            seed: Seed {
+                tile_id: Some(self.tiling.clamp_seed_tile(self.seed_tile)),
+                use_patch: self.seed_patch,
+                rotation: self.seed_rotation,
+                position: self.seed_position.into(),
+            },
+            merge_edges: self.merge_shared_edges,
+            merge_tolerance: self.merge_tolerance,
+            optimize_travel: self.optimize_pen_travel,
+            clip,
+            mask,
+            supertiles: self.supertiles.clone(),
+            layer_split: LayerSplit {
+                prototile: self.layers_by_prototile,
+                orientation: self.layers_by_orientation,
+                parent: self.layers_by_parent,
+            },
+            coloring: self.coloring,
+            decorations: self.draw_decorations,
+            growth: self.grow_from_labels.then(|| GrowthSettings {
+                max_backtracks: self.grow_backtracks,
+                rotations: self.grow_rotations,
+                reflections: self.grow_reflections,
+                seed: self.grow_seed.into(),
+            }),
+            multigrid,
+            verbose: false,
+        })
+    }
+}
+
 impl App for TilingSketch {
     fn update(&mut self, sketch: &mut Sketch, _ctx: &mut Context) -> anyhow::Result<()> {
         sketch.scale(Unit::Mm);
         sketch.stroke_width(self.line_thickness);
 
-        let mut shapes: Vec<TilePlacement> = Vec::new();
-        let before = Instant::now();
-        let bounds = Rect::from_center_size(self.offset, (self.width, self.height));
-
-        let scale = if self.fixed_size_max_level {
-            self.initial_scale
-        } else {
-            self.initial_scale * self.tiling.expansion_factor.powi(self.levels as i32)
+        let settings = match self.render_settings() {
+            Ok(settings) => settings,
+            Err(err) => {
+                self.status = ExpansionStatus::warning(err.to_string());
+                sketch.rect(0f64, 0f64, self.width, self.height);
+                return Ok(());
+            }
+        };
+        self.generator.request(&self.tiling, &settings);
+        self.generator.poll();
+        self.status = match self.generator.state() {
+            JobState::Idle => ExpansionStatus::default(),
+            JobState::Running {
+                level,
+                levels,
+                tiles,
+            } => {
+                let current = (level + 1).min(levels);
+                ExpansionStatus::running(
+                    format!("Generating level {current} of {levels}, {tiles} tiles"),
+                    level as f32 / levels.max(1) as f32,
+                )
+            }
+            JobState::Finished(Ok(rendered)) => match rendered.truncation_warning(&settings) {
+                Some(warning) => ExpansionStatus::warning(warning),
+                None => ExpansionStatus::info(format!(
+                    "{} tiles at level {}",
+                    rendered.expansion.tiles, rendered.expansion.level_reached
+                )),
+            },
+            JobState::Finished(Err(err)) => {
+                ExpansionStatus::warning(format!("Can't generate tiling: {err}"))
+            }
+            JobState::Failed => ExpansionStatus::warning(String::from("Generator thread crashed")),
         };
-
-        self.tiling
-            .expand_0_levels(self.levels, scale, Some(bounds), &mut shapes);
-        println!("Generate time: {:.2?}", before.elapsed());
-        let before = Instant::now();
-        let path = self.tiling.to_bez_path(&shapes);
-        println!("Convert to path time: {:.2?}", before.elapsed());
         let before = Instant::now();
-        sketch
-            .push_matrix()
-            .translate(-self.offset.x(), -self.offset.y())
-            .add_path(path)
-            .pop_matrix();
+        for layer in self.generator.layers() {
+            sketch.set_layer(layer.layer);
+            let [r, g, b] = layer.color.unwrap_or([0, 0, 0]);
+            sketch.color(Color::new(r, g, b, 255));
+            sketch.add_path(layer.path.clone());
+        }
         println!("Sketch time: {:.2?}", before.elapsed());
 
+        sketch.set_layer(0);
+        sketch.color(Color::BLACK);
         sketch.rect(0f64, 0f64, self.width, self.height);
+        if settings.clip.is_some() && !matches!(settings.mask, MaskShape::Frame) {
+            sketch.add_path(settings.clip_mask().to_path());
+        }
+        // Supertile levels go on the layers after the tile layers.
+        let first_free = self.generator.layers().iter().map(|layer| layer.layer + 1).max();
+        let first_free = first_free.unwrap_or(1);
+        for supertiles in self.generator.supertiles() {
+            sketch.set_layer(first_free + supertiles.level - 1);
+            sketch.stroke_width(supertiles.line_thickness);
+            sketch.add_path(supertiles.path.clone());
+        }
+        sketch.set_layer(0);
         Ok(())
     }
 }
 
 fn main() -> Result {
-    let SQUARE_GRID: TilingStep = TilingStep {
-        rules: vec![TilingRule {
-            tile: Tile {
-                corners: vec![
-                    Point { x: 0f64, y: 0f64 },
-                    Point { x: 0f64, y: 1f64 },
-                    Point { x: 1f64, y: 1f64 },
-                    Point { x: 1f64, y: 0f64 },
-                ],
-            },
-            result: vec![
-                TilePlacement {
-                    tile_id: 0,
-                    transform: (Affine::scale(0.5)).pre_translate(Vec2::new(0.0, 0.0)),
-                },
-                TilePlacement {
-                    tile_id: 0,
-                    transform: (Affine::scale(0.5)).then_translate(Vec2::new(0.5, 0.0)),
-                },
-                TilePlacement {
-                    tile_id: 0,
-                    transform: (Affine::scale(0.5)).then_translate(Vec2::new(0.0, 0.5)),
-                },
-                TilePlacement {
-                    tile_id: 0,
-                    transform: (Affine::scale(0.5)).then_translate(Vec2::new(0.5, 0.5)),
-                },
-            ],
-        }],
-        expansion_factor: 2.0,
-    };
-
-    let soc_scale = 1.0 / (1.0 + 2.0 * 36_f64.to_radians().cos());
-    let socd1 = 36_f64.to_radians().sin_cos();
-    let soc_corn1 = Vec2::new(-socd1.0, socd1.1);
-    let soc_corn2 = Vec2::new(0.0, 2.0 * socd1.1);
-    let soc_corn3 = Vec2::from(socd1);
-    let soc_p4 = (soc_corn2 + Vec2::new(0.0, 1.0)) * soc_scale;
-    let socd2 = 18_f64.to_radians().sin_cos();
-    let soc_cornb1 = Vec2::new(-socd2.0, socd2.1);
-    let soc_cornb2 = Vec2::new(0.0, 2.0 * socd2.1);
-    let soc_cornb3 = Vec2::from(socd2);
-    let socolar_5 = TilingStep {
-        rules: vec![
-            TilingRule {
-                tile: Tile::rhombus(1.0, 72.0),
-                result: vec![
-                    TilePlacement {
-                        tile_id: 0,
-                        transform: (Affine::scale(soc_scale)),
-                    },
-                    TilePlacement {
-                        tile_id: 0,
-                        transform: (Affine::scale(soc_scale))
-                            .then_rotate(-144_f64.to_radians())
-                            .then_translate(soc_corn1),
-                    },
-                    TilePlacement {
-                        tile_id: 0,
-                        transform: (Affine::scale(soc_scale))
-                            .then_rotate(-72_f64.to_radians())
-                            .then_translate(soc_corn1),
-                    },
-                    TilePlacement {
-                        tile_id: 0,
-                        transform: (Affine::scale(soc_scale))
-                            .then_rotate(72_f64.to_radians())
-                            .then_translate(soc_corn3),
-                    },
-                    TilePlacement {
-                        tile_id: 0,
-                        transform: (Affine::scale(soc_scale))
-                            .then_rotate(144_f64.to_radians())
-                            .then_translate(soc_corn2),
-                    },
-                    TilePlacement {
-                        tile_id: 1,
-                        transform: (Affine::scale(soc_scale))
-                            .then_rotate(162_f64.to_radians())
-                            .then_translate(soc_p4),
-                    },
-                    TilePlacement {
-                        tile_id: 1,
-                        transform: (Affine::scale(soc_scale))
-                            .then_rotate(18_f64.to_radians())
-                            .then_translate(soc_corn3 * soc_scale),
-                    },
-                    TilePlacement {
-                        tile_id: 1,
-                        transform: (Affine::scale(soc_scale))
-                            .then_rotate(90_f64.to_radians())
-                            .then_translate(soc_corn3 + 1.0 * (soc_corn1 * soc_scale)),
-                    },
-                ],
-            },
-            TilingRule {
-                tile: Tile::rhombus(1.0, 36.0),
-                result: vec![
-                    TilePlacement {
-                        tile_id: 0,
-                        transform: (Affine::scale(soc_scale))
-                            .then_rotate(-18_f64.to_radians())
-                            .then_translate(Vec2::default()),
-                    },
-                    TilePlacement {
-                        tile_id: 0,
-                        transform: (Affine::scale(soc_scale))
-                            .then_rotate(-162_f64.to_radians())
-                            .then_translate(soc_cornb1),
-                    },
-                    TilePlacement {
-                        tile_id: 0,
-                        transform: (Affine::scale(soc_scale))
-                            .then_rotate(-90_f64.to_radians())
-                            .then_translate(soc_cornb1),
-                    },
-                    TilePlacement {
-                        tile_id: 1,
-                        transform: (Affine::scale(soc_scale))
-                            .then_rotate(144_f64.to_radians())
-                            .then_translate(soc_cornb3),
-                    },
-                    TilePlacement {
-                        tile_id: 1,
-                        transform: (Affine::scale(soc_scale))
-                            .then_rotate(-144_f64.to_radians())
-                            .then_translate(soc_cornb2 - soc_cornb3 * soc_scale),
-                    },
-                ],
-            },
-        ],
-        expansion_factor: 1.0 / soc_scale,
-    };
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("render") {
+        if let Err(err) = render_cli::run(&args[1..]) {
+            eprintln!("error: {err}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
     let mut data = TilingSketch::default();
-    data.tiling = socolar_5;
+    data.tiling = presets::load_preset("socolar_5").unwrap();
+    data.seed_tile = data.tiling.default_seed_tile();
     Runner::new(data)
         .with_page_size_options(PageSize::A5H)
         .with_layout_options(LayoutOptions::Center)
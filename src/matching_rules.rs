@@ -0,0 +1,131 @@
+use kurbo::Point;
+
+use crate::coloring::shared_edges;
+use crate::tiling::*;
+
+/// Two neighbouring tiles whose shared edge breaks the edge labels.
+#[derive(Clone, Debug)]
+pub struct LabelViolation {
+    /// Tile index and edge index of both tiles.
+    pub a: (usize, usize),
+    pub b: (usize, usize),
+    pub location: Point,
+}
+
+impl EdgeLabel {
+    /// Whether two edges may meet. `same_direction` when both run along the shared edge the same
+    /// way, which decides whether their arrows agree.
+    pub fn fits(&self, other: &EdgeLabel, same_direction: bool) -> bool {
+        if self.kind == 0 || other.kind == 0 {
+            return true;
+        }
+        if self.kind != other.kind {
+            return false;
+        }
+        use EdgeMarking::*;
+        match (self.marking, other.marking) {
+            (Plain, Plain) | (In, Out) | (Out, In) => true,
+            (Forward, Forward) | (Backward, Backward) => same_direction,
+            (Forward, Backward) | (Backward, Forward) => !same_direction,
+            _ => false,
+        }
+    }
+}
+
+impl TilingStep {
+    /// Every pair of neighbouring tiles in `tiles` that breaks the edge labels, so a patch
+    /// expanded from the rules tells whether the substitution respects the matching rules.
+    pub fn check_edge_labels(
+        &self,
+        tiles: &[TilePlacement],
+        tolerance: f64,
+    ) -> Vec<LabelViolation> {
+        let label = |(tile, edge): (usize, usize)| {
+            self.rules
+                .get(tiles[tile].tile_id)
+                .map(|rule| rule.tile.label(edge))
+                .unwrap_or_default()
+        };
+        let mut result = Vec::new();
+        for shared in shared_edges(self, tiles, tolerance) {
+            if !label(shared.a).fits(&label(shared.b), shared.same_direction) {
+                result.push(LabelViolation {
+                    a: shared.a,
+                    b: shared.b,
+                    location: shared.location,
+                });
+            }
+        }
+        return result;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets;
+    use kurbo::{Affine, Vec2};
+
+    fn label(kind: u32, marking: EdgeMarking) -> EdgeLabel {
+        return EdgeLabel { kind, marking };
+    }
+
+    /// Unit squares whose left and right edges carry the given labels.
+    fn squares(left: EdgeLabel, right: EdgeLabel) -> TilingStep {
+        let mut tiling = presets::load_preset("square_grid").unwrap();
+        *tiling.rules[0].tile.label_mut(0) = left;
+        *tiling.rules[0].tile.label_mut(2) = right;
+        return tiling;
+    }
+
+    fn placement(transform: Affine) -> TilePlacement {
+        return TilePlacement {
+            tile_id: 0,
+            transform,
+        };
+    }
+
+    #[test]
+    fn fits_checks_kinds_and_markings() {
+        use EdgeMarking::*;
+        assert!(label(0, Forward).fits(&label(3, In), false));
+        assert!(!label(1, Plain).fits(&label(2, Plain), false));
+        assert!(label(1, Plain).fits(&label(1, Plain), true));
+        assert!(!label(1, Plain).fits(&label(1, Forward), true));
+
+        assert!(label(1, In).fits(&label(1, Out), false));
+        assert!(label(1, Out).fits(&label(1, In), true));
+        assert!(!label(1, In).fits(&label(1, In), false));
+        assert!(!label(1, Out).fits(&label(1, Out), true));
+
+        assert!(label(1, Forward).fits(&label(1, Forward), true));
+        assert!(!label(1, Forward).fits(&label(1, Forward), false));
+        assert!(label(1, Forward).fits(&label(1, Backward), false));
+        assert!(!label(1, Backward).fits(&label(1, Forward), true));
+    }
+
+    #[test]
+    fn finds_broken_labels() {
+        let arrow = label(1, EdgeMarking::Forward);
+        let beside = Affine::translate(Vec2::new(1.0, 0.0));
+        let tiles = [placement(Affine::IDENTITY), placement(beside)];
+
+        // The right edge runs down and the left edge up, so equal arrows point opposite ways.
+        let tiling = squares(arrow, arrow);
+        let violations = tiling.check_edge_labels(&tiles, 1e-6);
+        assert_eq!(violations.len(), 1);
+        let mut pair = [violations[0].a, violations[0].b];
+        pair.sort();
+        assert_eq!(pair, [(0, 2), (1, 0)]);
+        assert!(violations[0].location.distance(Point::new(1.0, 0.5)) < 1e-9);
+
+        let tiling = squares(arrow, label(1, EdgeMarking::Backward));
+        assert!(tiling.check_edge_labels(&tiles, 1e-6).is_empty());
+
+        // Mirrored, both right edges run down side by side.
+        let mirrored = Affine::new([-1.0, 0.0, 0.0, 1.0, 2.0, 0.0]);
+        let tiles = [placement(Affine::IDENTITY), placement(mirrored)];
+        let tiling = squares(label(2, EdgeMarking::Plain), arrow);
+        assert!(tiling.check_edge_labels(&tiles, 1e-6).is_empty());
+    }
+}
@@ -0,0 +1,387 @@
+use std::f64::consts::PI;
+use std::fmt;
+
+use kurbo::{Affine, Point, Rect, Vec2};
+
+use crate::culling::convex_polygon_intersects_rect;
+use crate::progress::Progress;
+use crate::tiling::*;
+
+/// Rhombus corners closer than this to a prototile corner angle, in radians, use that prototile.
+const ANGLE_TOLERANCE: f64 = 1e-3;
+/// Relative difference allowed between the side lengths of a rhombus prototile.
+const SIDE_TOLERANCE: f64 = 1e-3;
+
+/// De Bruijn's multigrid: `symmetry` families of parallel lines, one rhombus per crossing of
+/// two lines. See `TilingStep::multigrid`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MultigridSettings {
+    /// Odd n gives n grids and even n gives n / 2, e.g. 5 for Penrose and 8 for Ammann-Beenker.
+    pub symmetry: usize,
+    /// Shift γ of each grid, grids without one use `default_offsets`. Offsets where three grid
+    /// lines meet give overlapping rhombi.
+    pub offsets: Vec<f64>,
+}
+
+impl Default for MultigridSettings {
+    fn default() -> Self {
+        Self {
+            symmetry: 5,
+            offsets: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct OffsetError(String);
+
+impl fmt::Display for OffsetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid multigrid offset: {}", self.0)
+    }
+}
+
+impl std::error::Error for OffsetError {}
+
+/// Offsets separated by commas or whitespace.
+pub fn parse_offsets(text: &str) -> Result<Vec<f64>, OffsetError> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse().map_err(|_| OffsetError(part.to_string())))
+        .collect()
+}
+
+/// Fractional parts of the square roots of the first primes. They have no rational relations,
+/// so no three grid lines meet in a point.
+fn generic_offsets(count: usize) -> Vec<f64> {
+    let mut primes: Vec<u64> = Vec::new();
+    let mut candidate = 2;
+    while primes.len() < count {
+        if primes.iter().all(|p| candidate % p != 0) {
+            primes.push(candidate);
+        }
+        candidate += 1;
+    }
+    return primes.iter().map(|&p| (p as f64).sqrt().fract()).collect();
+}
+
+impl MultigridSettings {
+    /// Unit normals of the grid lines, an n-th of a turn apart.
+    pub fn directions(&self) -> Vec<Vec2> {
+        let n = self.symmetry.max(1);
+        let grids = if n % 2 == 1 { n } else { n / 2 };
+        return (0..grids)
+            .map(|j| Vec2::from_angle(2.0 * PI * j as f64 / n as f64))
+            .collect();
+    }
+
+    /// Generic offsets, for the pentagrid shifted to add up to zero, which is the condition for
+    /// the Penrose rhombus tiling.
+    pub fn default_offsets(&self) -> Vec<f64> {
+        let grids = self.directions().len();
+        let offsets = generic_offsets(grids);
+        if self.symmetry != 5 {
+            return offsets;
+        }
+        let mean = offsets.iter().sum::<f64>() / grids as f64;
+        return offsets.iter().map(|x| x - mean).collect();
+    }
+
+    pub fn grid_offsets(&self) -> Vec<f64> {
+        let mut offsets = self.default_offsets();
+        for (offset, given) in offsets.iter_mut().zip(&self.offsets) {
+            *offset = *given;
+        }
+        return offsets;
+    }
+}
+
+/// A prototile the rhombi with one corner angle are mapped onto.
+struct RhombusPrototile {
+    tile_id: usize,
+    /// Acute corner and the edges leaving it, in counter-clockwise order.
+    frame: Affine,
+    angle: f64,
+    side: f64,
+}
+
+/// Affine map taking the unit vectors to `u` and `v` and the origin to `corner`.
+fn frame(corner: Point, u: Vec2, v: Vec2) -> Affine {
+    Affine::new([u.x, u.y, v.x, v.y, corner.x, corner.y])
+}
+
+/// The edges at a corner of a parallelogram, ordered so that their cross product is positive.
+fn ordered(u: Vec2, v: Vec2) -> (Vec2, Vec2) {
+    if u.cross(v) >= 0.0 {
+        return (u, v);
+    }
+    return (v, u);
+}
+
+fn rhombus_prototile(tile_id: usize, tile: &Tile) -> Option<RhombusPrototile> {
+    let corners = &tile.corners;
+    if corners.len() != 4 {
+        return None;
+    }
+    let sides: Vec<f64> = (0..4)
+        .map(|i| (corners[(i + 1) % 4] - corners[i]).hypot())
+        .collect();
+    let side = sides[0];
+    if side <= 0.0
+        || sides
+            .iter()
+            .any(|s| (s - side).abs() > SIDE_TOLERANCE * side)
+    {
+        return None;
+    }
+    // First corner with an angle of at most 90°, edges in counter-clockwise order.
+    let (corner, u, v) = (0..4).find_map(|i| {
+        let next = corners[(i + 1) % 4] - corners[i];
+        let prev = corners[(i + 3) % 4] - corners[i];
+        let (u, v) = ordered(next, prev);
+        (u.dot(v) >= 0.0).then_some((corners[i], u, v))
+    })?;
+    return Some(RhombusPrototile {
+        tile_id,
+        frame: frame(corner, u, v),
+        angle: u.cross(v).atan2(u.dot(v)),
+        side,
+    });
+}
+
+impl TilingStep {
+    /// Rhombus tiling of `bounds` from de Bruijn's multigrid, with the rhombi mapped onto the
+    /// rhombus prototiles with matching corner angles. The rhombus prototiles need a common
+    /// side, which the rhombi get times `scale`. No substitution is involved, so any window of
+    /// the tiling can be generated directly.
+    pub fn multigrid(
+        &self,
+        bounds: Rect,
+        scale: f64,
+        max_tiles: usize,
+        settings: &MultigridSettings,
+        progress: &Progress,
+    ) -> Result<(Vec<TilePlacement>, ExpansionResult), TilingError> {
+        self.validate()?;
+        if !(scale.is_finite() && scale > 0.0) {
+            return Err(TilingError::InvalidMultigridScale(scale));
+        }
+        let prototiles: Vec<RhombusPrototile> = self
+            .rules
+            .iter()
+            .enumerate()
+            .filter_map(|(i, rule)| rhombus_prototile(i, &rule.tile))
+            .collect();
+        if let Some(first) = prototiles.first() {
+            let mismatched = prototiles[1..].iter().find(|prototile| {
+                (prototile.side - first.side).abs() > SIDE_TOLERANCE * first.side
+            });
+            if let Some(prototile) = mismatched {
+                return Err(TilingError::MismatchedRhombusSide {
+                    rule: prototile.tile_id,
+                    side: prototile.side,
+                    expected: first.side,
+                });
+            }
+        }
+        let directions = settings.directions();
+        let offsets = settings.grid_offsets();
+        let grids = directions.len();
+        let mut result = ExpansionResult::default();
+        let mut tiles = Vec::new();
+        let side = scale * prototiles.first().map_or(1.0, |prototile| prototile.side);
+
+        // A rhombus has its corners at side * Σ (K_j - γ_j) e_j, which is within side * grids
+        // of side * grids / 2 times the grid crossing it belongs to.
+        let margin = side * (grids + 2) as f64;
+        let search = bounds
+            .inflate(margin, margin)
+            .scale_from_origin(2.0 / (side * grids as f64));
+        let search_corners = [
+            Point::new(search.x0, search.y0),
+            Point::new(search.x1, search.y0),
+            Point::new(search.x1, search.y1),
+            Point::new(search.x0, search.y1),
+        ];
+        let line_range = |j: usize| {
+            let values = search_corners
+                .iter()
+                .map(|z| z.to_vec2().dot(directions[j]) + offsets[j]);
+            let min = values.clone().fold(f64::INFINITY, f64::min);
+            let max = values.fold(f64::NEG_INFINITY, f64::max);
+            (min.floor() as i64)..=(max.ceil() as i64)
+        };
+        let prototile_for = |u: Vec2, v: Vec2| {
+            let angle = u.cross(v).atan2(u.dot(v));
+            prototiles
+                .iter()
+                .find(|prototile| (prototile.angle - angle).abs() <= ANGLE_TOLERANCE)
+                .ok_or(TilingError::MissingRhombus(angle.to_degrees()))
+        };
+        // Every pair of grids gives rhombi with one acute angle. Check them all up front, so
+        // bounds that happen to contain no rhombi don't hide a missing prototile.
+        for r in 0..grids {
+            for s in r + 1..grids {
+                let (u, v) = ordered(directions[r], directions[s]);
+                let (u, v) = if u.dot(v) >= 0.0 { (u, v) } else { (v, -u) };
+                prototile_for(u, v)?;
+            }
+        }
+
+        'grids: for r in 0..grids {
+            for s in r + 1..grids {
+                let (er, es) = (directions[r], directions[s]);
+                let det = er.cross(es);
+                for kr in line_range(r) {
+                    progress.check()?;
+                    progress.set_tiles(tiles.len());
+                    for ks in line_range(s) {
+                        // Crossing of line kr of grid r with line ks of grid s.
+                        let a = kr as f64 - offsets[r];
+                        let b = ks as f64 - offsets[s];
+                        let z =
+                            Point::new((a * es.y - b * er.y) / det, (er.x * b - es.x * a) / det);
+                        if !search.contains(z) {
+                            continue;
+                        }
+                        let mut base = a * er + b * es;
+                        for j in (0..grids).filter(|&j| j != r && j != s) {
+                            let k = (z.to_vec2().dot(directions[j]) + offsets[j]).ceil();
+                            base += (k - offsets[j]) * directions[j];
+                        }
+                        let base = (base * side).to_point();
+                        let (u, v) = ordered(er * side, es * side);
+                        let corners = [base, base + u, base + u + v, base + v];
+                        if !convex_polygon_intersects_rect(&corners, bounds) {
+                            continue;
+                        }
+                        // Acute corner, on the other side of the first edge when the corner at
+                        // the base is obtuse.
+                        let (corner, u, v) = if u.dot(v) >= 0.0 {
+                            (base, u, v)
+                        } else {
+                            (base + u, v, -u)
+                        };
+                        if tiles.len() >= max_tiles {
+                            result.truncated = true;
+                            break 'grids;
+                        }
+                        let prototile = prototile_for(u, v)?;
+                        tiles.push(TilePlacement {
+                            tile_id: prototile.tile_id,
+                            transform: frame(corner, u, v) * prototile.frame.inverse(),
+                        });
+                    }
+                }
+            }
+        }
+        result.tiles = tiles.len();
+        return Ok((tiles, result));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets;
+
+    fn pentagrid(tiling: &TilingStep) -> Result<Vec<TilePlacement>, TilingError> {
+        let bounds = Rect::new(-4.0, -4.0, 4.0, 4.0);
+        let settings = MultigridSettings::default();
+        let (tiles, result) =
+            tiling.multigrid(bounds, 1.0, 10000, &settings, &Progress::default())?;
+        assert!(!result.truncated);
+        return Ok(tiles);
+    }
+
+    fn corners(tiling: &TilingStep, tile: &TilePlacement) -> Vec<Point> {
+        let corners = &tiling.rules[tile.tile_id].tile.corners;
+        return corners.iter().map(|p| tile.transform * *p).collect();
+    }
+
+    /// Whether the interiors of two convex polygons overlap by more than `margin`, i.e. no edge
+    /// of either separates them.
+    fn overlap(a: &[Point], b: &[Point], margin: f64) -> bool {
+        let separated_by = |polygon: &[Point]| {
+            (0..polygon.len()).any(|i| {
+                let edge = polygon[(i + 1) % polygon.len()] - polygon[i];
+                let normal = Vec2::new(-edge.y, edge.x) / edge.hypot();
+                let range = |points: &[Point]| {
+                    let values = points.iter().map(|p| p.to_vec2().dot(normal));
+                    let min = values.clone().fold(f64::INFINITY, f64::min);
+                    (min, values.fold(f64::NEG_INFINITY, f64::max))
+                };
+                let ((a0, a1), (b0, b1)) = (range(a), range(b));
+                a1 <= b0 + margin || b1 <= a0 + margin
+            })
+        };
+        return !separated_by(a) && !separated_by(b);
+    }
+
+    #[test]
+    fn penrose_rhombi() {
+        let tiling = presets::load_preset("socolar_5").unwrap();
+        let tiles = pentagrid(&tiling).unwrap();
+        assert!(!tiles.is_empty());
+        let mut angles = Vec::new();
+        for tile in &tiles {
+            let corners = corners(&tiling, tile);
+            let (u, v) = (corners[1] - corners[0], corners[3] - corners[0]);
+            let angle = u.cross(v).abs().atan2(u.dot(v)).to_degrees();
+            let acute = angle.min(180.0 - angle);
+            assert!(
+                (acute - 36.0).abs() < 1e-6 || (acute - 72.0).abs() < 1e-6,
+                "rhombus with a {acute}° corner"
+            );
+            angles.push(acute.round() as i64);
+        }
+        assert!(angles.contains(&36) && angles.contains(&72));
+
+        let polygons: Vec<Vec<Point>> = tiles.iter().map(|tile| corners(&tiling, tile)).collect();
+        for i in 0..polygons.len() {
+            for j in i + 1..polygons.len() {
+                assert!(
+                    !overlap(&polygons[i], &polygons[j], 1e-6),
+                    "tiles {i} and {j} overlap"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn needs_rhombus_prototiles() {
+        let mut tiling = presets::load_preset("square_grid").unwrap();
+        tiling.rules[0].tile.corners = vec![
+            Point::new(0.0, 0.0),
+            Point::new(1.0, 0.0),
+            Point::new(0.0, 1.0),
+        ];
+        assert!(matches!(
+            pentagrid(&tiling),
+            Err(TilingError::MissingRhombus(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_scales() {
+        let tiling = presets::load_preset("socolar_5").unwrap();
+        let bounds = Rect::new(-4.0, -4.0, 4.0, 4.0);
+        let settings = MultigridSettings::default();
+        for scale in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let tiles = tiling.multigrid(bounds, scale, 10000, &settings, &Progress::default());
+            assert!(matches!(tiles, Err(TilingError::InvalidMultigridScale(_))));
+        }
+    }
+
+    #[test]
+    fn rhombi_need_one_side() {
+        let mut tiling = presets::load_preset("socolar_5").unwrap();
+        for p in &mut tiling.rules[1].tile.corners {
+            *p = (p.to_vec2() * 2.0).to_point();
+        }
+        assert!(matches!(
+            pentagrid(&tiling),
+            Err(TilingError::MismatchedRhombusSide { rule: 1, .. })
+        ));
+    }
+}
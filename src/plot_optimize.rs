@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+
+use kurbo::{BezPath, ParamCurve, PathEl, PathSeg, Point};
+
+use crate::progress::{Progress, PROGRESS_INTERVAL};
+use crate::tiling::TilingError;
+
+/// Maximum distance in the stroke order between the two ends of a 2-opt reversal.
+const TWO_OPT_WINDOW: usize = 32;
+const TWO_OPT_PASSES: usize = 4;
+/// Upper bound on grid cells along the longer side of the end point bounds.
+const MAX_GRID_CELLS: f64 = 4096.0;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OptimizeStats {
+    pub segments: usize,
+    pub polylines: usize,
+    pub travel_before: f64,
+    pub travel_after: f64,
+}
+
+/// Total pen-up distance between consecutive subpaths of `path`.
+pub fn path_travel(path: &BezPath) -> f64 {
+    let mut travel = 0.0;
+    let mut current: Option<Point> = None;
+    let mut subpath_start = Point::ZERO;
+    for el in path.elements() {
+        match *el {
+            PathEl::MoveTo(p) => {
+                if let Some(current) = current {
+                    travel += current.distance(p);
+                }
+                subpath_start = p;
+                current = Some(p);
+            }
+            PathEl::LineTo(p) | PathEl::QuadTo(_, p) | PathEl::CurveTo(_, _, p) => {
+                current = Some(p);
+            }
+            PathEl::ClosePath => current = Some(subpath_start),
+        }
+    }
+    return travel;
+}
+
+struct VertexIndex {
+    tolerance: f64,
+    cells: HashMap<(i64, i64), Vec<usize>>,
+    points: Vec<Point>,
+}
+
+impl VertexIndex {
+    fn new(tolerance: f64) -> VertexIndex {
+        VertexIndex {
+            tolerance: tolerance.max(f64::EPSILON),
+            cells: HashMap::new(),
+            points: Vec::new(),
+        }
+    }
+
+    fn cell(&self, p: Point) -> (i64, i64) {
+        (
+            (p.x / self.tolerance).floor() as i64,
+            (p.y / self.tolerance).floor() as i64,
+        )
+    }
+
+    fn find_or_insert(&mut self, p: Point) -> usize {
+        let (cx, cy) = self.cell(p);
+        for x in cx - 1..=cx + 1 {
+            for y in cy - 1..=cy + 1 {
+                if let Some(ids) = self.cells.get(&(x, y)) {
+                    for id in ids {
+                        if self.points[*id].distance(p) <= self.tolerance {
+                            return *id;
+                        }
+                    }
+                }
+            }
+        }
+        let id = self.points.len();
+        self.points.push(p);
+        self.cells.entry((cx, cy)).or_default().push(id);
+        return id;
+    }
+}
+
+/// Joins segments that share end points into the longest trails found by a greedy walk over the
+/// edge graph. Walks start at odd degree vertices since every trail has to end at one of those.
+pub fn chain_segments(segments: &[PathSeg], tolerance: f64) -> Vec<Vec<PathSeg>> {
+    let mut vertices = VertexIndex::new(tolerance);
+    let mut ends = Vec::with_capacity(segments.len());
+    let mut adjacency: Vec<Vec<usize>> = Vec::new();
+    for (i, seg) in segments.iter().enumerate() {
+        let a = vertices.find_or_insert(seg.start());
+        let b = vertices.find_or_insert(seg.end());
+        adjacency.resize(vertices.points.len(), Vec::new());
+        adjacency[a].push(i);
+        adjacency[b].push(i);
+        ends.push((a, b));
+    }
+
+    let mut used = vec![false; segments.len()];
+    let mut next_edge = vec![0usize; adjacency.len()];
+    let mut result = Vec::new();
+    let odd_first = (0..adjacency.len())
+        .filter(|v| adjacency[*v].len() % 2 == 1)
+        .chain((0..adjacency.len()).filter(|v| adjacency[*v].len() % 2 == 0));
+    for start in odd_first {
+        loop {
+            let mut trail = Vec::new();
+            let mut vertex = start;
+            while let Some(edge) = next_unused(&adjacency[vertex], &mut next_edge[vertex], &used) {
+                used[edge] = true;
+                let (a, b) = ends[edge];
+                if a == vertex {
+                    trail.push(segments[edge]);
+                    vertex = b;
+                } else {
+                    trail.push(segments[edge].reverse());
+                    vertex = a;
+                }
+            }
+            if trail.is_empty() {
+                break;
+            }
+            result.push(trail);
+        }
+    }
+    return result;
+}
+
+fn next_unused(edges: &[usize], cursor: &mut usize, used: &[bool]) -> Option<usize> {
+    while *cursor < edges.len() {
+        let edge = edges[*cursor];
+        if !used[edge] {
+            return Some(edge);
+        }
+        *cursor += 1;
+    }
+    return None;
+}
+
+fn stroke_start(stroke: &[PathSeg]) -> Point {
+    stroke.first().map(|seg| seg.start()).unwrap_or_default()
+}
+
+fn stroke_end(stroke: &[PathSeg]) -> Point {
+    stroke.last().map(|seg| seg.end()).unwrap_or_default()
+}
+
+fn reverse_stroke(stroke: &mut Vec<PathSeg>) {
+    stroke.reverse();
+    for seg in stroke.iter_mut() {
+        *seg = seg.reverse();
+    }
+}
+
+/// Uniform grid over stroke end points for nearest neighbour queries.
+struct EndpointGrid {
+    cell_size: f64,
+    origin: Point,
+    max_cell: (i64, i64),
+    cells: HashMap<(i64, i64), Vec<(usize, bool)>>,
+}
+
+impl EndpointGrid {
+    fn new(strokes: &[Vec<PathSeg>]) -> EndpointGrid {
+        let first = stroke_start(&strokes[0]);
+        let mut bounds = kurbo::Rect::from_points(first, first);
+        for stroke in strokes {
+            bounds = bounds.union_pt(stroke_start(stroke)).union_pt(stroke_end(stroke));
+        }
+        // Sized by the longer side so that collinear or long thin outputs don't end up with
+        // vanishing cells.
+        let extent = bounds.width().max(bounds.height());
+        let cell_size = if extent > 0.0 {
+            (extent / (strokes.len() as f64).sqrt()).max(extent / MAX_GRID_CELLS)
+        } else {
+            1.0
+        };
+        let mut grid = EndpointGrid {
+            cell_size,
+            origin: bounds.origin(),
+            max_cell: (0, 0),
+            cells: HashMap::new(),
+        };
+        grid.max_cell = grid.cell(Point::new(bounds.x1, bounds.y1));
+        for (i, stroke) in strokes.iter().enumerate() {
+            let start = grid.cell(stroke_start(stroke));
+            grid.cells.entry(start).or_default().push((i, false));
+            let end = grid.cell(stroke_end(stroke));
+            grid.cells.entry(end).or_default().push((i, true));
+        }
+        return grid;
+    }
+
+    fn cell(&self, p: Point) -> (i64, i64) {
+        (
+            ((p.x - self.origin.x) / self.cell_size).floor() as i64,
+            ((p.y - self.origin.y) / self.cell_size).floor() as i64,
+        )
+    }
+
+    /// Closest end point of a stroke that isn't used yet, and whether it is the stroke's end.
+    /// Searches rings of cells around `p`, widening until a ring is further away than the best
+    /// point found so far or the whole grid is covered.
+    fn nearest(&self, p: Point, strokes: &[Vec<PathSeg>], used: &[bool]) -> Option<(usize, bool)> {
+        let (cx, cy) = self.cell(p);
+        let (mx, my) = self.max_cell;
+        // Rings closer than `min_ring` lie entirely outside the grid.
+        let min_ring = (-cx).max(cx - mx).max(-cy).max(cy - my).max(0);
+        let max_ring = cx.abs().max((mx - cx).abs()).max(cy.abs()).max((my - cy).abs());
+        let mut best: Option<(f64, usize, bool)> = None;
+        for ring in min_ring..=max_ring {
+            // Everything in further rings is at least this far away.
+            let ring_distance = (ring - 1).max(0) as f64 * self.cell_size;
+            if let Some((distance, _, _)) = best {
+                if distance < ring_distance {
+                    break;
+                }
+            }
+            for cell in ring_cells((cx, cy), ring, self.max_cell) {
+                let Some(items) = self.cells.get(&cell) else {
+                    continue;
+                };
+                for (stroke, at_end) in items {
+                    if used[*stroke] {
+                        continue;
+                    }
+                    let q = if *at_end {
+                        stroke_end(&strokes[*stroke])
+                    } else {
+                        stroke_start(&strokes[*stroke])
+                    };
+                    let distance = p.distance(q);
+                    if best.map_or(true, |(d, _, _)| distance < d) {
+                        best = Some((distance, *stroke, *at_end));
+                    }
+                }
+            }
+        }
+        return best.map(|(_, stroke, at_end)| (stroke, at_end));
+    }
+}
+
+/// Cells on the border of the square of cells `ring` steps around `center` that lie inside
+/// `(0, 0)..=max_cell`.
+fn ring_cells(
+    center: (i64, i64),
+    ring: i64,
+    max_cell: (i64, i64),
+) -> impl Iterator<Item = (i64, i64)> {
+    let (cx, cy) = center;
+    let (mx, my) = max_cell;
+    // A ring of zero is the centre cell alone, which is both its top and bottom row.
+    let sides = if ring == 0 { 1 } else { 2 };
+    let rows = [cy - ring, cy + ring]
+        .into_iter()
+        .take(sides)
+        .filter(move |y| (0..=my).contains(y))
+        .flat_map(move |y| ((cx - ring).max(0)..=(cx + ring).min(mx)).map(move |x| (x, y)));
+    let columns = [cx - ring, cx + ring]
+        .into_iter()
+        .take(sides)
+        .filter(move |x| (0..=mx).contains(x))
+        .flat_map(move |x| ((cy - ring + 1).max(0)..(cy + ring).min(my + 1)).map(move |y| (x, y)));
+    return rows.chain(columns);
+}
+
+/// Orders strokes with a nearest neighbour pass followed by windowed 2-opt, reversing strokes
+/// where that shortens the travel.
+pub fn order_strokes(
+    strokes: Vec<Vec<PathSeg>>,
+    progress: &Progress,
+) -> Result<Vec<Vec<PathSeg>>, TilingError> {
+    if strokes.is_empty() {
+        return Ok(strokes);
+    }
+    let grid = EndpointGrid::new(&strokes);
+    let mut used = vec![false; strokes.len()];
+    let mut order: Vec<(usize, bool)> = Vec::with_capacity(strokes.len());
+    let mut position = Point::ZERO;
+    while let Some((stroke, at_end)) = grid.nearest(position, &strokes, &used) {
+        if order.len() % PROGRESS_INTERVAL == 0 {
+            progress.check()?;
+        }
+        used[stroke] = true;
+        order.push((stroke, at_end));
+        position = if at_end {
+            stroke_start(&strokes[stroke])
+        } else {
+            stroke_end(&strokes[stroke])
+        };
+    }
+
+    let mut strokes: Vec<Option<Vec<PathSeg>>> = strokes.into_iter().map(Some).collect();
+    let mut ordered: Vec<Vec<PathSeg>> = order
+        .into_iter()
+        .map(|(i, reversed)| {
+            let mut stroke = strokes[i].take().unwrap();
+            if reversed {
+                reverse_stroke(&mut stroke);
+            }
+            stroke
+        })
+        .collect();
+    two_opt(&mut ordered, progress)?;
+    return Ok(ordered);
+}
+
+fn two_opt(strokes: &mut [Vec<PathSeg>], progress: &Progress) -> Result<(), TilingError> {
+    let n = strokes.len();
+    for _pass in 0..TWO_OPT_PASSES {
+        let mut improved = false;
+        for i in 1..n {
+            if i % PROGRESS_INTERVAL == 0 {
+                progress.check()?;
+            }
+            let before = stroke_end(&strokes[i - 1]);
+            for j in i + 1..n.min(i + TWO_OPT_WINDOW) {
+                let first = stroke_start(&strokes[i]);
+                let last = stroke_end(&strokes[j]);
+                let after = strokes.get(j + 1).map(|s| stroke_start(s));
+                let old_cost = before.distance(first) + after.map_or(0.0, |a| last.distance(a));
+                let new_cost = before.distance(last) + after.map_or(0.0, |a| first.distance(a));
+                if new_cost + 1e-9 < old_cost {
+                    strokes[i..=j].reverse();
+                    for stroke in &mut strokes[i..=j] {
+                        reverse_stroke(stroke);
+                    }
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+    return Ok(());
+}
+
+pub fn strokes_to_path(strokes: &[Vec<PathSeg>]) -> BezPath {
+    let mut result = BezPath::new();
+    for stroke in strokes {
+        result.move_to(stroke_start(stroke));
+        for seg in stroke {
+            match seg {
+                PathSeg::Line(line) => result.line_to(line.p1),
+                PathSeg::Quad(quad) => result.quad_to(quad.p1, quad.p2),
+                PathSeg::Cubic(cubic) => result.curve_to(cubic.p1, cubic.p2, cubic.p3),
+            }
+        }
+    }
+    return result;
+}
+
+pub fn optimize_path(
+    path: &BezPath,
+    tolerance: f64,
+    progress: &Progress,
+) -> Result<(BezPath, OptimizeStats), TilingError> {
+    let segments: Vec<PathSeg> = path.segments().collect();
+    let strokes = order_strokes(chain_segments(&segments, tolerance), progress)?;
+    let result = strokes_to_path(&strokes);
+    let stats = OptimizeStats {
+        segments: segments.len(),
+        polylines: strokes.len(),
+        travel_before: path_travel(path),
+        travel_after: path_travel(&result),
+    };
+    return Ok((result, stats));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kurbo::Line;
+
+    fn stroke(a: Point, b: Point) -> Vec<PathSeg> {
+        vec![PathSeg::Line(Line::new(a, b))]
+    }
+
+    fn order(strokes: Vec<Vec<PathSeg>>) -> Vec<Vec<PathSeg>> {
+        order_strokes(strokes, &Progress::default()).unwrap()
+    }
+
+    #[test]
+    fn orders_collinear_strokes() {
+        let strokes: Vec<Vec<PathSeg>> = (0..5000)
+            .rev()
+            .map(|i| stroke(Point::new(i as f64, 0.0), Point::new(i as f64 + 0.5, 0.0)))
+            .collect();
+        let ordered = order(strokes);
+        assert_eq!(ordered.len(), 5000);
+        let travel = path_travel(&strokes_to_path(&ordered));
+        assert!(travel < 5000.0, "travel {travel}");
+    }
+
+    #[test]
+    fn orders_identical_end_points() {
+        let p = Point::new(3.0, 4.0);
+        let strokes: Vec<Vec<PathSeg>> = (0..100).map(|_| stroke(p, p)).collect();
+        assert_eq!(order(strokes).len(), 100);
+    }
+
+    #[test]
+    fn orders_long_thin_strokes() {
+        let strokes: Vec<Vec<PathSeg>> = (0..2000)
+            .map(|i| {
+                let x = (i * 7919 % 2000) as f64 * 10.0;
+                stroke(Point::new(x, 0.0), Point::new(x + 1.0, 0.001))
+            })
+            .collect();
+        assert_eq!(order(strokes).len(), 2000);
+    }
+
+    #[test]
+    fn chains_a_closed_square() {
+        let corners = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)].map(Point::from);
+        // Shuffled, with one segment running against the others.
+        let segments = [
+            PathSeg::Line(Line::new(corners[1], corners[2])),
+            PathSeg::Line(Line::new(corners[0], corners[1])),
+            PathSeg::Line(Line::new(corners[3], corners[0])),
+            PathSeg::Line(Line::new(corners[3], corners[2])),
+        ];
+        let chains = chain_segments(&segments, 1e-6);
+        assert_eq!(chains.len(), 1);
+        let chain = &chains[0];
+        assert_eq!(chain.len(), 4);
+        for (i, seg) in chain.iter().enumerate() {
+            assert_eq!(seg.end(), chain[(i + 1) % 4].start());
+        }
+    }
+
+    #[test]
+    fn reaches_strokes_far_from_the_rest() {
+        let mut strokes: Vec<Vec<PathSeg>> = (0..5000)
+            .map(|i| {
+                let p = Point::new((i % 100) as f64 * 0.01, (i / 100) as f64 * 0.01);
+                stroke(p, p + (0.005, 0.0))
+            })
+            .collect();
+        let far = Point::new(100.0, 100.0);
+        strokes.push(stroke(far, far + (1.0, 0.0)));
+        let ordered = order(strokes);
+        assert_eq!(ordered.len(), 5001);
+        assert_eq!(stroke_start(ordered.last().unwrap()), far);
+    }
+}
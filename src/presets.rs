@@ -0,0 +1,16 @@
+use crate::tiling::TilingStep;
+
+pub const PRESETS: &[(&str, &str)] = &[
+    ("socolar_5", include_str!("../tilings/socolar_5.ron")),
+    ("square_grid", include_str!("../tilings/square_grid.ron")),
+];
+
+pub fn preset_names() -> impl Iterator<Item = &'static str> {
+    PRESETS.iter().map(|(name, _)| *name)
+}
+
+pub fn load_preset(name: &str) -> Option<TilingStep> {
+    let (_, text) = PRESETS.iter().find(|(preset, _)| *preset == name)?;
+    // Presets are compiled in, so a broken one is a programming error.
+    Some(TilingStep::from_ron_str(text).expect("invalid built-in tiling preset"))
+}
@@ -0,0 +1,55 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::tiling::TilingError;
+
+/// Items handled between cancellation checks in loops over single tiles or strokes.
+pub const PROGRESS_INTERVAL: usize = 4096;
+
+/// Shared between a generation job and whoever waits for it. Long running loops check
+/// `check` between chunks of work and give up with `TilingError::Cancelled`.
+#[derive(Default)]
+pub struct Progress {
+    cancelled: AtomicBool,
+    level: AtomicUsize,
+    tiles: AtomicUsize,
+}
+
+impl Progress {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn check(&self) -> Result<(), TilingError> {
+        if self.is_cancelled() {
+            return Err(TilingError::Cancelled);
+        }
+        return Ok(());
+    }
+
+    /// Number of completed levels.
+    pub fn level(&self) -> usize {
+        self.level.load(Ordering::Relaxed)
+    }
+
+    pub fn set_level(&self, level: usize) {
+        self.level.store(level, Ordering::Relaxed);
+    }
+
+    /// Tiles produced so far for the level being expanded.
+    pub fn tiles(&self) -> usize {
+        self.tiles.load(Ordering::Relaxed)
+    }
+
+    pub fn set_tiles(&self, tiles: usize) {
+        self.tiles.store(tiles, Ordering::Relaxed);
+    }
+
+    /// Raises the tile count, parallel workers report their totals in any order.
+    pub fn report_tiles(&self, tiles: usize) {
+        self.tiles.fetch_max(tiles, Ordering::Relaxed);
+    }
+}
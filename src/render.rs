@@ -0,0 +1,645 @@
+use std::fmt::Write as _;
+use std::time::Instant;
+
+use kurbo::{Affine, BezPath, Point, Rect, Shape};
+
+use crate::address::TileAddress;
+use crate::clip::{ClipMask, ClipMode};
+use crate::coloring::{color_tiles, ColoringScheme};
+use crate::culling::Culler;
+use crate::edges::dedup_path;
+use crate::expansion::LevelOptions;
+use crate::growth::GrowthSettings;
+use crate::layers::{LayerBuilder, LayerPath, LayerSplit};
+use crate::multigrid::MultigridSettings;
+use crate::plot_optimize::optimize_path;
+use crate::progress::{Progress, PROGRESS_INTERVAL};
+use crate::tiling::*;
+
+#[derive(Clone, PartialEq)]
+pub enum MaskShape {
+    Frame,
+    /// Largest circle that fits the frame.
+    Circle,
+    /// Arbitrary shape in page coordinates.
+    Custom(ClipMask),
+}
+
+/// Outlines of the tiles `level` substitutions above the drawn ones.
+#[derive(Clone, PartialEq)]
+pub struct SupertileLayer {
+    pub enabled: bool,
+    pub level: usize,
+    pub line_thickness: f64,
+}
+
+impl Default for SupertileLayer {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            level: 1,
+            line_thickness: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Default, PartialEq)]
+pub struct SupertileLayers {
+    pub layers: Vec<SupertileLayer>,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct RenderSettings {
+    pub width: f64,
+    pub height: f64,
+    pub offset: Point,
+    pub line_thickness: f64,
+    pub initial_scale: f64,
+    pub fixed_size_max_level: bool,
+    pub levels: usize,
+    pub seed: Seed,
+    pub polygon_limit: usize,
+    /// Stream tiles from the depth-first expander instead of building every level in memory.
+    pub depth_first: bool,
+    pub parallel: bool,
+    pub merge_edges: bool,
+    pub merge_tolerance: f64,
+    pub optimize_travel: bool,
+    /// Clip the output to `mask`, `None` draws every generated tile.
+    pub clip: Option<ClipMode>,
+    pub mask: MaskShape,
+    pub supertiles: SupertileLayers,
+    /// Splitting by parent needs tile addresses, see `needs_hierarchy`.
+    pub layer_split: LayerSplit,
+    /// Colours tiles onto one layer per colour.
+    pub coloring: ColoringScheme,
+    /// Draw the decorations of every tile on a separate layer.
+    pub decorations: bool,
+    /// Grow the tiles from the edge labels instead of substituting, `polygon_limit` still caps
+    /// the number of tiles.
+    pub growth: Option<GrowthSettings>,
+    /// Rhombus tiling from de Bruijn's multigrid instead of substituting or growing, tiles have
+    /// the size of `initial_scale`.
+    pub multigrid: Option<MultigridSettings>,
+    /// Report the time and statistics of every stage on stderr.
+    pub verbose: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            width: 100.0,
+            height: 100.0,
+            offset: Point::ZERO,
+            line_thickness: 0.5,
+            initial_scale: 1.0,
+            fixed_size_max_level: false,
+            levels: 5,
+            seed: Seed::default(),
+            polygon_limit: DEFAULT_POLYGON_LIMIT,
+            depth_first: false,
+            parallel: true,
+            merge_edges: false,
+            merge_tolerance: 0.001,
+            optimize_travel: false,
+            clip: None,
+            mask: MaskShape::Frame,
+            supertiles: SupertileLayers::default(),
+            layer_split: LayerSplit::default(),
+            coloring: ColoringScheme::None,
+            decorations: true,
+            growth: None,
+            multigrid: None,
+            verbose: false,
+        }
+    }
+}
+
+impl RenderSettings {
+    pub fn bounds(&self) -> Rect {
+        Rect::from_center_size(self.offset, (self.width, self.height))
+    }
+
+    /// Maps tiling coordinates to the page, where the frame spans (0, 0) to (width, height).
+    pub fn page_transform(&self) -> Affine {
+        Affine::translate(Point::new(self.width * 0.5, self.height * 0.5) - self.offset)
+    }
+
+    pub fn frame(&self) -> Rect {
+        Rect::new(0.0, 0.0, self.width, self.height)
+    }
+
+    pub fn clip_mask(&self) -> ClipMask {
+        match &self.mask {
+            MaskShape::Frame => ClipMask::rect(self.frame()),
+            MaskShape::Circle => ClipMask::circle(
+                self.frame().center(),
+                self.width.min(self.height) * 0.5,
+            ),
+            MaskShape::Custom(mask) => mask.clone(),
+        }
+    }
+
+    /// Whether the output depends on the tile addresses. Those are only tracked by
+    /// `expand_tiles_with_hierarchy`, so depth-first streaming and the tile cache are bypassed.
+    pub fn needs_hierarchy(&self) -> bool {
+        self.substitutes() && (self.layer_split.parent || self.coloring.needs_addresses())
+    }
+
+    /// Grown and multigrid patches have no levels, so they are neither streamed, cached nor
+    /// previewed.
+    pub fn substitutes(&self) -> bool {
+        self.growth.is_none() && self.multigrid.is_none()
+    }
+
+    /// Colouring needs every tile and its neighbours at once, which streaming can't provide.
+    pub fn can_stream(&self) -> bool {
+        !self.needs_hierarchy() && self.coloring == ColoringScheme::None
+    }
+
+    pub fn seed_scale(&self, tiling: &TilingStep) -> f64 {
+        if self.fixed_size_max_level {
+            self.initial_scale
+        } else {
+            self.initial_scale * tiling.expansion_factor.powi(self.levels as i32)
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SupertilePath {
+    pub level: usize,
+    pub line_thickness: f64,
+    /// In page coordinates, see `RenderSettings::page_transform`.
+    pub path: BezPath,
+}
+
+pub struct Rendered {
+    /// A single layer unless `RenderSettings::layer_split` splits it.
+    pub layers: Vec<LayerPath>,
+    pub expansion: ExpansionResult,
+    pub supertiles: Vec<SupertilePath>,
+}
+
+impl Rendered {
+    pub fn truncation_warning(&self, settings: &RenderSettings) -> Option<String> {
+        if !self.expansion.truncated {
+            return None;
+        }
+        if settings.multigrid.is_some() {
+            return Some(format!(
+                "polygon limit {} reached, the multigrid doesn't cover the frame",
+                settings.polygon_limit
+            ));
+        }
+        if !settings.substitutes() {
+            return Some(format!(
+                "polygon limit {} reached while growing",
+                settings.polygon_limit
+            ));
+        }
+        Some(format!(
+            "polygon limit {} reached at level {} of {}, drawing {} tiles",
+            settings.polygon_limit,
+            self.expansion.level_reached,
+            settings.levels,
+            self.expansion.tiles
+        ))
+    }
+}
+
+fn stream_layers(
+    tiling: &TilingStep,
+    settings: &RenderSettings,
+    progress: &Progress,
+) -> Result<(Vec<LayerPath>, ExpansionResult), TilingError> {
+    let before = Instant::now();
+    let mut expansion = ExpansionResult {
+        tiles: 0,
+        level_reached: settings.levels,
+        truncated: false,
+    };
+    let tiles = tiling
+        .try_iter_expand_seed(
+            &settings.seed,
+            settings.levels,
+            settings.seed_scale(tiling),
+            Some(settings.bounds()),
+        )?
+        .take_while(|_| {
+            if expansion.tiles % PROGRESS_INTERVAL == 0 {
+                progress.set_tiles(expansion.tiles);
+                if progress.is_cancelled() {
+                    return false;
+                }
+            }
+            if expansion.tiles >= settings.polygon_limit {
+                expansion.truncated = true;
+                return false;
+            }
+            expansion.tiles += 1;
+            true
+        });
+    let mut layers = LayerBuilder::new(tiling, settings.layer_split, settings.decorations);
+    for tile in tiles {
+        layers.add(&tile, None, None);
+    }
+    progress.check()?;
+    if settings.verbose {
+        eprintln!("Generate and convert to path time: {:.2?}", before.elapsed());
+    }
+    return Ok((layers.finish(settings, progress)?, expansion));
+}
+
+pub fn expand_tiles(
+    tiling: &TilingStep,
+    settings: &RenderSettings,
+    progress: &Progress,
+) -> Result<(Vec<TilePlacement>, ExpansionResult), TilingError> {
+    tiling.validate()?;
+    tiling.validate_seed(&settings.seed)?;
+    let before = Instant::now();
+    let input = tiling.seed_placements(&settings.seed, settings.seed_scale(tiling));
+    let options = LevelOptions {
+        max_tiles: Some(settings.polygon_limit),
+        parallel: settings.parallel,
+        progress,
+    };
+    let bounds = Some(settings.bounds());
+    let expanded = tiling.expand_visible(&input, settings.levels, bounds, &options);
+    if settings.verbose {
+        eprintln!("Generate time: {:.2?}", before.elapsed());
+    }
+    return expanded;
+}
+
+/// Seed placements and tile addresses of an expansion, see `TilingStep::expand_with_addresses`.
+pub struct Hierarchy {
+    pub input: Vec<TilePlacement>,
+    pub addresses: Vec<TileAddress>,
+}
+
+impl Hierarchy {
+    /// Tile id of every tile's parent, `None` for tiles that weren't expanded at all.
+    pub fn parents(&self, tiling: &TilingStep) -> Vec<Option<usize>> {
+        self.addresses
+            .iter()
+            .map(|address| {
+                let ids = tiling.address_tile_ids(&self.input, address)?;
+                ids.len().checked_sub(2).map(|i| ids[i])
+            })
+            .collect()
+    }
+}
+
+/// Same as `expand_tiles` along with the hierarchy addresses of the tiles. `on_level` gets every
+/// completed level.
+pub fn expand_tiles_with_hierarchy(
+    tiling: &TilingStep,
+    settings: &RenderSettings,
+    progress: &Progress,
+    on_level: impl FnMut(usize, &[(TilePlacement, TileAddress)]),
+) -> Result<(Vec<TilePlacement>, Hierarchy, ExpansionResult), TilingError> {
+    tiling.validate()?;
+    tiling.validate_seed(&settings.seed)?;
+    let before = Instant::now();
+    let input = tiling.seed_placements(&settings.seed, settings.seed_scale(tiling));
+    let culler = Culler::new(tiling, settings.bounds());
+    let options = LevelOptions {
+        max_tiles: Some(settings.polygon_limit),
+        parallel: settings.parallel,
+        progress,
+    };
+    let expand = |tile: &TilePlacement| culler.subtree_visible(tile);
+    let (tiles, mut expansion) =
+        tiling.expand_with_addresses(&input, settings.levels, &options, expand, on_level)?;
+    let (shapes, addresses): (Vec<TilePlacement>, Vec<TileAddress>) = tiles
+        .into_iter()
+        .filter(|(tile, _)| culler.tile_visible(tiling, tile))
+        .unzip();
+    expansion.tiles = shapes.len();
+    if settings.verbose {
+        eprintln!("Generate time: {:.2?}", before.elapsed());
+    }
+    return Ok((shapes, Hierarchy { input, addresses }, expansion));
+}
+
+/// Converts tiles to one finished path per output layer. `hierarchy` is needed when splitting by
+/// parent or colouring by ancestor, see `RenderSettings::needs_hierarchy`.
+pub fn tile_layers(
+    tiling: &TilingStep,
+    tiles: &[TilePlacement],
+    hierarchy: Option<&Hierarchy>,
+    settings: &RenderSettings,
+    progress: &Progress,
+) -> Result<Vec<LayerPath>, TilingError> {
+    tiling.validate_placements(tiles)?;
+    let before = Instant::now();
+    let coloring = color_tiles(
+        tiling,
+        tiles,
+        hierarchy.map(|hierarchy| hierarchy.addresses.as_slice()),
+        settings.coloring,
+        settings.merge_tolerance,
+        progress,
+    )?;
+    if let Some(coloring) = coloring.as_ref().filter(|_| settings.verbose) {
+        eprintln!("{} colours in {:.2?}", coloring.count, before.elapsed());
+    }
+    let parents = match hierarchy {
+        Some(hierarchy) if settings.layer_split.parent => Some(hierarchy.parents(tiling)),
+        _ => None,
+    };
+    let before = Instant::now();
+    let mut layers = LayerBuilder::new(tiling, settings.layer_split, settings.decorations);
+    for (i, tile) in tiles.iter().enumerate() {
+        if i % PROGRESS_INTERVAL == 0 {
+            progress.check()?;
+        }
+        let parent = parents.as_ref().and_then(|parents| parents[i]);
+        let color = coloring.as_ref().map(|coloring| coloring.colors[i]);
+        layers.add(tile, parent, color);
+    }
+    if settings.verbose {
+        eprintln!("Convert to path time: {:.2?}", before.elapsed());
+    }
+    return layers.finish(settings, progress);
+}
+
+/// Tiles grown from the seed by `TilingStep::grow`, at the size of `initial_scale`.
+pub fn grow_tiles(
+    tiling: &TilingStep,
+    settings: &RenderSettings,
+    growth: &GrowthSettings,
+    progress: &Progress,
+) -> Result<(Vec<TilePlacement>, ExpansionResult), TilingError> {
+    tiling.validate_seed(&settings.seed)?;
+    let before = Instant::now();
+    let seed = tiling.seed_placements(&settings.seed, settings.initial_scale);
+    let (tiles, result) = tiling.grow(
+        &seed,
+        settings.bounds(),
+        settings.polygon_limit,
+        growth,
+        progress,
+    )?;
+    if settings.verbose {
+        eprintln!(
+            "Grew {} tiles with {} backtracks{} in {:.2?}",
+            result.tiles,
+            result.backtracks,
+            if result.complete { "" } else { ", bounds not covered" },
+            before.elapsed()
+        );
+    }
+    let expansion = ExpansionResult {
+        tiles: result.tiles,
+        level_reached: 0,
+        truncated: result.truncated,
+    };
+    return Ok((tiles, expansion));
+}
+
+/// Rhombi of the multigrid covering the frame, see `TilingStep::multigrid`.
+pub fn multigrid_tiles(
+    tiling: &TilingStep,
+    settings: &RenderSettings,
+    multigrid: &MultigridSettings,
+    progress: &Progress,
+) -> Result<(Vec<TilePlacement>, ExpansionResult), TilingError> {
+    let before = Instant::now();
+    let (tiles, result) = tiling.multigrid(
+        settings.bounds(),
+        settings.initial_scale,
+        settings.polygon_limit,
+        multigrid,
+        progress,
+    )?;
+    if settings.verbose {
+        eprintln!(
+            "Multigrid of {} grids gave {} tiles in {:.2?}",
+            multigrid.directions().len(),
+            result.tiles,
+            before.elapsed()
+        );
+    }
+    return Ok((tiles, result));
+}
+
+fn expand_layers(
+    tiling: &TilingStep,
+    settings: &RenderSettings,
+    progress: &Progress,
+) -> Result<(Vec<LayerPath>, ExpansionResult), TilingError> {
+    if let Some(multigrid) = &settings.multigrid {
+        let (shapes, expansion) = multigrid_tiles(tiling, settings, multigrid, progress)?;
+        let layers = tile_layers(tiling, &shapes, None, settings, progress)?;
+        return Ok((layers, expansion));
+    }
+    if let Some(growth) = &settings.growth {
+        let (shapes, expansion) = grow_tiles(tiling, settings, growth, progress)?;
+        let layers = tile_layers(tiling, &shapes, None, settings, progress)?;
+        return Ok((layers, expansion));
+    }
+    if settings.needs_hierarchy() {
+        let (shapes, hierarchy, expansion) =
+            expand_tiles_with_hierarchy(tiling, settings, progress, |_, _| {})?;
+        let layers = tile_layers(tiling, &shapes, Some(&hierarchy), settings, progress)?;
+        return Ok((layers, expansion));
+    }
+    let (shapes, expansion) = expand_tiles(tiling, settings, progress)?;
+    let layers = tile_layers(tiling, &shapes, None, settings, progress)?;
+    return Ok((layers, expansion));
+}
+
+/// Moves a path in tiling coordinates onto the page and applies clipping and the plotter
+/// optimizations.
+pub fn finish_path(
+    mut path: BezPath,
+    settings: &RenderSettings,
+    progress: &Progress,
+) -> Result<BezPath, TilingError> {
+    path.apply_affine(settings.page_transform());
+    if let Some(mode) = settings.clip {
+        progress.check()?;
+        let before = Instant::now();
+        let stats;
+        (path, stats) = settings.clip_mask().clip_path(&path, mode);
+        if settings.verbose {
+            eprintln!(
+                "Kept {} of {} tiles inside the mask in {:.2?}",
+                stats.output_tiles,
+                stats.input_tiles,
+                before.elapsed()
+            );
+        }
+    }
+    if settings.merge_edges {
+        progress.check()?;
+        let before = Instant::now();
+        let stats;
+        (path, stats) = dedup_path(&path, settings.merge_tolerance);
+        if settings.verbose {
+            eprintln!(
+                "Removed {} of {} segments as duplicates in {:.2?}",
+                stats.removed(),
+                stats.input_segments,
+                before.elapsed()
+            );
+        }
+    }
+    if settings.optimize_travel {
+        let before = Instant::now();
+        let stats;
+        (path, stats) = optimize_path(&path, settings.merge_tolerance, progress)?;
+        if settings.verbose {
+            eprintln!(
+                "Joined {} segments into {} polylines, pen travel {:.1} -> {:.1} in {:.2?}",
+                stats.segments,
+                stats.polylines,
+                stats.travel_before,
+                stats.travel_after,
+                before.elapsed()
+            );
+        }
+    }
+    return Ok(path);
+}
+
+/// Outlines of the enabled supertile levels, counted from the level the main expansion reached.
+pub fn supertile_paths(
+    tiling: &TilingStep,
+    settings: &RenderSettings,
+    level_reached: usize,
+    progress: &Progress,
+) -> Result<Vec<SupertilePath>, TilingError> {
+    let enabled: Vec<&SupertileLayer> = settings
+        .supertiles
+        .layers
+        .iter()
+        .filter(|layer| layer.enabled && layer.level > 0 && layer.level <= level_reached)
+        .collect();
+    let Some(lowest) = enabled.iter().map(|layer| layer.level).min() else {
+        return Ok(Vec::new());
+    };
+    tiling.validate()?;
+    tiling.validate_seed(&settings.seed)?;
+    let before = Instant::now();
+    let input = tiling.seed_placements(&settings.seed, settings.seed_scale(tiling));
+    let (levels, _) = tiling.expand_all_levels(
+        &input,
+        level_reached - lowest,
+        Some(settings.bounds()),
+        Some(settings.polygon_limit),
+        progress,
+    )?;
+    let mut result = Vec::new();
+    for layer in enabled {
+        let Some(tiles) = levels.get(level_reached - layer.level) else {
+            continue;
+        };
+        let path = tiling.to_bez_path(tiles);
+        result.push(SupertilePath {
+            level: layer.level,
+            line_thickness: layer.line_thickness,
+            path: finish_path(path, settings, progress)?,
+        });
+    }
+    if settings.verbose {
+        eprintln!("Supertile time: {:.2?}", before.elapsed());
+    }
+    return Ok(result);
+}
+
+/// Every tile layer and supertile outline for `settings`. Fails with `TilingError::Cancelled` once
+/// `progress` is cancelled.
+pub fn generate_path(
+    tiling: &TilingStep,
+    settings: &RenderSettings,
+    progress: &Progress,
+) -> Result<Rendered, TilingError> {
+    let streams = settings.depth_first && settings.can_stream() && settings.substitutes();
+    let (layers, expansion) = if streams {
+        stream_layers(tiling, settings, progress)?
+    } else {
+        expand_layers(tiling, settings, progress)?
+    };
+    let supertiles = supertile_paths(tiling, settings, expansion.level_reached, progress)?;
+    return Ok(Rendered {
+        layers,
+        expansion,
+        supertiles,
+    });
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Produces the same drawing as `TilingSketch` as a standalone SVG document in millimeters.
+pub fn to_svg(tiling: &TilingStep, settings: &RenderSettings) -> Result<String, TilingError> {
+    let rendered = generate_path(tiling, settings, &Progress::default())?;
+    if let Some(warning) = rendered.truncation_warning(settings) {
+        eprintln!("warning: {warning}");
+    }
+    let frame = settings.frame();
+    let mut view = frame;
+    for layer in &rendered.layers {
+        view = view.union(layer.path.bounding_box());
+    }
+    for supertiles in &rendered.supertiles {
+        view = view.union(supertiles.path.bounding_box());
+    }
+    view = view.inflate(settings.line_thickness, settings.line_thickness);
+
+    let mut out = String::new();
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}mm" height="{h}mm" viewBox="{x} {y} {w} {h}">"#,
+        x = view.x0,
+        y = view.y0,
+        w = view.width(),
+        h = view.height(),
+    )
+    .unwrap();
+    writeln!(
+        out,
+        r#"<g fill="none" stroke="black" stroke-width="{}" stroke-linejoin="round">"#,
+        settings.line_thickness
+    )
+    .unwrap();
+    for layer in &rendered.layers {
+        match layer.color {
+            Some([r, g, b]) => writeln!(
+                out,
+                r#"<g id="layer-{}" stroke="rgb({r},{g},{b})"><title>{}</title>"#,
+                layer.layer,
+                xml_escape(&layer.name)
+            )
+            .unwrap(),
+            None => writeln!(out, "<g>").unwrap(),
+        }
+        writeln!(out, r#"<path d="{}"/>"#, layer.path.to_svg()).unwrap();
+        writeln!(out, "</g>").unwrap();
+    }
+    writeln!(out, r#"<path d="{}"/>"#, frame.to_path(0.1).to_svg()).unwrap();
+    if settings.clip.is_some() && !matches!(settings.mask, MaskShape::Frame) {
+        writeln!(out, r#"<path d="{}"/>"#, settings.clip_mask().to_path().to_svg()).unwrap();
+    }
+    writeln!(out, "</g>").unwrap();
+    for supertiles in &rendered.supertiles {
+        writeln!(
+            out,
+            r#"<g id="supertiles-{}" fill="none" stroke="black" stroke-width="{}" stroke-linejoin="round">"#,
+            supertiles.level, supertiles.line_thickness
+        )
+        .unwrap();
+        writeln!(out, r#"<path d="{}"/>"#, supertiles.path.to_svg()).unwrap();
+        writeln!(out, "</g>").unwrap();
+    }
+    writeln!(out, "</svg>").unwrap();
+    return Ok(out);
+}
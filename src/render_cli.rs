@@ -0,0 +1,345 @@
+use std::error::Error;
+
+use kurbo::Point;
+
+use crate::clip::{ClipMask, ClipMode};
+use crate::coloring::ColoringScheme;
+use crate::growth::GrowthSettings;
+use crate::layers::LayerSplit;
+use crate::multigrid::{parse_offsets, MultigridSettings};
+use crate::presets;
+use crate::render::*;
+use crate::tiling::TilingStep;
+
+const USAGE: &str = "\
+usage: tile-draw render [options] --output FILE.svg
+
+  --preset NAME            built-in rule set (default: socolar_5)
+  --rules FILE             load rules from a .ron tiling file instead of a preset
+  --levels N               number of substitution levels (default: 5)
+  --scale F                initial scale (default: 1.0)
+  --fixed-size-max-level   don't grow the seed tile with the number of levels
+  --seed-tile N            prototile to start the expansion from (default: 1 if it exists)
+  --seed-patch             start from the seed patch stored with the rules
+  --seed-rotation DEG      rotation of the seed (default: 0)
+  --seed-position X,Y      position of the seed (default: 0,0)
+  --polygon-limit N        stop expanding when a level would exceed N tiles (default: 1000000)
+  --depth-first            stream tiles depth-first instead of keeping whole levels in memory
+  --serial                 expand on a single thread
+  --width F, --height F    size of the drawn area in mm (default: 100)
+  --offset X,Y             center of the drawn area (default: 0,0)
+  --line-thickness F       stroke width in mm (default: 0.5)
+  --merge-edges            draw edges shared by neighbouring tiles only once
+  --merge-tolerance F      distance below which edges count as coincident (default: 0.001)
+  --optimize-travel        join edges into polylines and reorder them to reduce pen travel
+  --clip MODE              trim: cut tiles at the mask border, whole-tiles: only keep tiles fully
+                           inside the mask
+  --mask SHAPE             frame (default), circle, or SVG path data in page mm
+  --mask-file FILE         read the mask as SVG path data from a file
+  --supertiles LIST        outline larger tiles, comma separated LEVEL[:WIDTH] entries where level
+                           1 is one substitution above the drawn tiles (default width: 1.0)
+  --split-layers LIST      put tiles on separate coloured layers, comma separated list of
+                           prototile, orientation and parent
+  --coloring SCHEME        put each tile colour on its own layer: proper (neighbours differ),
+                           prototile, orientation or ancestor[:LEVELS_UP]
+  --no-decorations         don't draw the decorations of the prototiles
+  --grow                   grow the tiles from the edge labels instead of substituting, tiles
+                           get the size of --scale and --polygon-limit caps their number
+  --backtracks N           dead ends undone while growing before giving up (default: 10000)
+  --translations-only      keep every grown tile in the seed orientation, e.g. for Wang tiles
+  --reflections            allow mirrored tiles while growing
+  --grow-seed N            order in which grown tiles are tried (default: 0)
+  --multigrid N            rhombus tiling with N-fold symmetry from de Bruijn's multigrid instead
+                           of substituting, e.g. 5 for Penrose or 8 for Ammann-Beenker, drawn with
+                           the rhombus prototiles at the size of --scale
+  --multigrid-offsets LIST comma separated offsets of the grids (default: generic, for N = 5 adding
+                           up to zero as needed for the Penrose tiling)
+  --output FILE            SVG file to write
+  --verbose                report the time and statistics of every stage on stderr
+";
+
+fn parse_value<T: std::str::FromStr>(name: &str, value: Option<&String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("missing value for {name}"))?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {name}: {value}"))
+}
+
+fn parse_clip_mode(name: &str, value: Option<&String>) -> Result<ClipMode, String> {
+    let value = value.ok_or_else(|| format!("missing value for {name}"))?;
+    match value.as_str() {
+        "trim" => Ok(ClipMode::Trim),
+        "whole-tiles" => Ok(ClipMode::WholeTiles),
+        _ => Err(format!("invalid value for {name}, expected trim or whole-tiles: {value}")),
+    }
+}
+
+fn parse_mask(name: &str, value: Option<&String>) -> Result<MaskShape, Box<dyn Error>> {
+    let value = value.ok_or_else(|| format!("missing value for {name}"))?;
+    match value.as_str() {
+        "frame" => Ok(MaskShape::Frame),
+        "circle" => Ok(MaskShape::Circle),
+        _ => Ok(MaskShape::Custom(ClipMask::from_svg(value)?)),
+    }
+}
+
+fn parse_supertiles(name: &str, value: Option<&String>) -> Result<SupertileLayers, String> {
+    let value = value.ok_or_else(|| format!("missing value for {name}"))?;
+    let invalid = || format!("invalid value for {name}, expected LEVEL[:WIDTH],...: {value}");
+    let mut result = SupertileLayers::default();
+    for entry in value.split(',') {
+        let mut layer = SupertileLayer::default();
+        let (level, width) = match entry.split_once(':') {
+            Some((level, width)) => (level, Some(width)),
+            None => (entry, None),
+        };
+        layer.level = level.trim().parse().map_err(|_| invalid())?;
+        if let Some(width) = width {
+            layer.line_thickness = width.trim().parse().map_err(|_| invalid())?;
+        }
+        result.layers.push(layer);
+    }
+    Ok(result)
+}
+
+fn parse_layer_split(name: &str, value: Option<&String>) -> Result<LayerSplit, String> {
+    let value = value.ok_or_else(|| format!("missing value for {name}"))?;
+    let mut result = LayerSplit::default();
+    for entry in value.split(',') {
+        match entry.trim() {
+            "prototile" => result.prototile = true,
+            "orientation" => result.orientation = true,
+            "parent" => result.parent = true,
+            _ => {
+                return Err(format!(
+                    "invalid value for {name}, expected prototile, orientation or parent: {entry}"
+                ))
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn parse_coloring(name: &str, value: Option<&String>) -> Result<ColoringScheme, String> {
+    let value = value.ok_or_else(|| format!("missing value for {name}"))?;
+    let invalid = || {
+        format!(
+            "invalid value for {name}, expected proper, prototile, orientation or \
+             ancestor[:LEVELS_UP]: {value}"
+        )
+    };
+    match value.split_once(':') {
+        Some(("ancestor", levels_up)) => Ok(ColoringScheme::Ancestor {
+            levels_up: levels_up.trim().parse().map_err(|_| invalid())?,
+        }),
+        Some(_) => Err(invalid()),
+        None => match value.as_str() {
+            "proper" => Ok(ColoringScheme::Proper),
+            "prototile" => Ok(ColoringScheme::Prototile),
+            "orientation" => Ok(ColoringScheme::Orientation),
+            "ancestor" => Ok(ColoringScheme::Ancestor { levels_up: 1 }),
+            _ => Err(invalid()),
+        },
+    }
+}
+
+fn parse_point(name: &str, value: Option<&String>) -> Result<Point, String> {
+    let value = value.ok_or_else(|| format!("missing value for {name}"))?;
+    let invalid = || format!("invalid value for {name}, expected X,Y: {value}");
+    let (x, y) = value.split_once(',').ok_or_else(invalid)?;
+    let x = x.trim().parse().map_err(|_| invalid())?;
+    let y = y.trim().parse().map_err(|_| invalid())?;
+    Ok(Point::new(x, y))
+}
+
+/// A parsed command line.
+struct Invocation {
+    settings: RenderSettings,
+    preset: String,
+    rules_file: Option<String>,
+    output: String,
+}
+
+/// `None` when only the usage was asked for.
+fn parse_args(args: &[String]) -> Result<Option<Invocation>, Box<dyn Error>> {
+    let mut settings = RenderSettings::default();
+    let mut preset = String::from("socolar_5");
+    let mut rules_file: Option<String> = None;
+    let mut output: Option<String> = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--preset" => preset = parse_value(arg, args.next())?,
+            "--rules" => rules_file = Some(parse_value(arg, args.next())?),
+            "--levels" => settings.levels = parse_value(arg, args.next())?,
+            "--scale" => settings.initial_scale = parse_value(arg, args.next())?,
+            "--fixed-size-max-level" => settings.fixed_size_max_level = true,
+            "--seed-tile" => settings.seed.tile_id = Some(parse_value(arg, args.next())?),
+            "--seed-patch" => settings.seed.use_patch = true,
+            "--seed-rotation" => settings.seed.rotation = parse_value(arg, args.next())?,
+            "--seed-position" => settings.seed.position = parse_point(arg, args.next())?,
+            "--polygon-limit" => settings.polygon_limit = parse_value(arg, args.next())?,
+            "--depth-first" => settings.depth_first = true,
+            "--serial" => settings.parallel = false,
+            "--width" => settings.width = parse_value(arg, args.next())?,
+            "--height" => settings.height = parse_value(arg, args.next())?,
+            "--offset" => settings.offset = parse_point(arg, args.next())?,
+            "--line-thickness" => settings.line_thickness = parse_value(arg, args.next())?,
+            "--merge-edges" => settings.merge_edges = true,
+            "--merge-tolerance" => settings.merge_tolerance = parse_value(arg, args.next())?,
+            "--optimize-travel" => settings.optimize_travel = true,
+            "--clip" => settings.clip = Some(parse_clip_mode(arg, args.next())?),
+            "--mask" => settings.mask = parse_mask(arg, args.next())?,
+            "--mask-file" => {
+                let path: String = parse_value(arg, args.next())?;
+                let data = std::fs::read_to_string(path)?;
+                settings.mask = MaskShape::Custom(ClipMask::from_svg(&data)?);
+            }
+            "--supertiles" => settings.supertiles = parse_supertiles(arg, args.next())?,
+            "--split-layers" => settings.layer_split = parse_layer_split(arg, args.next())?,
+            "--coloring" => settings.coloring = parse_coloring(arg, args.next())?,
+            "--no-decorations" => settings.decorations = false,
+            "--grow" => {
+                settings.growth.get_or_insert_with(GrowthSettings::default);
+            }
+            "--backtracks" => {
+                let growth = settings.growth.get_or_insert_with(GrowthSettings::default);
+                growth.max_backtracks = parse_value(arg, args.next())?;
+            }
+            "--translations-only" => {
+                let growth = settings.growth.get_or_insert_with(GrowthSettings::default);
+                growth.rotations = false;
+            }
+            "--reflections" => {
+                let growth = settings.growth.get_or_insert_with(GrowthSettings::default);
+                growth.reflections = true;
+            }
+            "--grow-seed" => {
+                let growth = settings.growth.get_or_insert_with(GrowthSettings::default);
+                growth.seed = parse_value(arg, args.next())?;
+            }
+            "--multigrid" => {
+                let multigrid = settings
+                    .multigrid
+                    .get_or_insert_with(MultigridSettings::default);
+                multigrid.symmetry = parse_value(arg, args.next())?;
+            }
+            "--multigrid-offsets" => {
+                let list: String = parse_value(arg, args.next())?;
+                let multigrid = settings
+                    .multigrid
+                    .get_or_insert_with(MultigridSettings::default);
+                multigrid.offsets = parse_offsets(&list)?;
+            }
+            "--verbose" | "-v" => settings.verbose = true,
+            "--output" | "-o" => output = Some(parse_value(arg, args.next())?),
+            "--help" | "-h" => return Ok(None),
+            _ => return Err(format!("unknown argument {arg}\n\n{USAGE}").into()),
+        }
+    }
+    let output = output.ok_or_else(|| format!("--output is required\n\n{USAGE}"))?;
+    return Ok(Some(Invocation {
+        settings,
+        preset,
+        rules_file,
+        output,
+    }));
+}
+
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let Some(Invocation {
+        settings,
+        preset,
+        rules_file,
+        output,
+    }) = parse_args(args)?
+    else {
+        print!("{USAGE}");
+        return Ok(());
+    };
+
+    let tiling = match rules_file {
+        Some(path) => TilingStep::load(path)?,
+        None => presets::load_preset(&preset).ok_or_else(|| {
+            let names = presets::preset_names().collect::<Vec<_>>().join(", ");
+            format!("unknown preset {preset}, available: {names}")
+        })?,
+    };
+
+    if let Some(warning) = tiling.expansion_factor_warning() {
+        eprintln!("warning: {warning}");
+    }
+    std::fs::write(&output, to_svg(&tiling, &settings)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::LayerPath;
+    use crate::progress::Progress;
+
+    fn parse(args: &str) -> Result<Option<Invocation>, Box<dyn Error>> {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        return parse_args(&args);
+    }
+
+    #[test]
+    fn parses_arguments() {
+        let invocation = parse(
+            "--preset square_grid --levels 3 --seed-tile 0 --seed-position 1,-2 \
+             --clip whole-tiles --coloring ancestor:2 --split-layers prototile,parent -o out.svg",
+        )
+        .unwrap()
+        .unwrap();
+        let settings = &invocation.settings;
+        assert_eq!(invocation.preset, "square_grid");
+        assert_eq!(invocation.output, "out.svg");
+        assert_eq!(settings.levels, 3);
+        assert_eq!(settings.seed.tile_id, Some(0));
+        assert_eq!(settings.seed.position, Point::new(1.0, -2.0));
+        assert_eq!(settings.clip, Some(ClipMode::WholeTiles));
+        assert_eq!(settings.coloring, ColoringScheme::Ancestor { levels_up: 2 });
+        assert!(settings.layer_split.prototile && settings.layer_split.parent);
+        assert!(!settings.layer_split.orientation);
+    }
+
+    #[test]
+    fn seed_tile_defaults_through_the_tiling() {
+        let invocation = parse("-o out.svg").unwrap().unwrap();
+        let tiling = presets::load_preset(&invocation.preset).unwrap();
+        let seed = &invocation.settings.seed;
+        assert_eq!(tiling.seed_tile(seed), tiling.default_seed_tile());
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse("--help").unwrap().is_none());
+        assert!(parse("--levels 3").is_err());
+        assert!(parse("--levels three -o out.svg").is_err());
+        assert!(parse("--clip sideways -o out.svg").is_err());
+        assert!(parse("--frobnicate -o out.svg").is_err());
+        assert!(parse("-o").is_err());
+    }
+
+    #[test]
+    fn renders_svg() {
+        let tiling = presets::load_preset("socolar_5").unwrap();
+        let settings = RenderSettings {
+            width: 20.0,
+            height: 20.0,
+            levels: 3,
+            clip: Some(ClipMode::WholeTiles),
+            ..Default::default()
+        };
+        let svg = to_svg(&tiling, &settings).unwrap();
+        // Only whole tiles inside the frame are left, so the view is the frame and its stroke.
+        assert!(svg.contains(r#"viewBox="-0.5 -0.5 21 21""#), "{svg}");
+        let rendered = generate_path(&tiling, &settings, &Progress::default()).unwrap();
+        let drawn = |layer: &LayerPath| !layer.path.elements().is_empty();
+        assert!(rendered.layers.iter().any(drawn));
+        // One path per layer and the frame.
+        assert_eq!(svg.matches("<path ").count(), rendered.layers.len() + 1);
+    }
+}
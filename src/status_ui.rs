@@ -0,0 +1,61 @@
+use whiskers::prelude::egui::Color32;
+use whiskers::widgets::Widget;
+use whiskers::{prelude::*, register_widget_ui};
+
+/// Read-only sketch parameter used to report what the last update did.
+#[derive(Clone, Default)]
+pub struct ExpansionStatus {
+    pub message: String,
+    pub warning: bool,
+    /// Fraction of the work done while generating in the background.
+    pub progress: Option<f32>,
+}
+
+impl ExpansionStatus {
+    pub fn info(message: String) -> ExpansionStatus {
+        ExpansionStatus {
+            message,
+            warning: false,
+            progress: None,
+        }
+    }
+
+    pub fn warning(message: String) -> ExpansionStatus {
+        ExpansionStatus {
+            message,
+            warning: true,
+            progress: None,
+        }
+    }
+
+    pub fn running(message: String, progress: f32) -> ExpansionStatus {
+        ExpansionStatus {
+            message,
+            warning: false,
+            progress: Some(progress),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ExpansionStatusWidget {}
+
+impl Widget<ExpansionStatus> for ExpansionStatusWidget {
+    fn ui(&self, ui: &mut egui::Ui, _label: &str, value: &mut ExpansionStatus) -> bool {
+        if value.warning {
+            ui.colored_label(Color32::RED, &value.message);
+        } else {
+            ui.label(&value.message);
+        }
+        if let Some(progress) = value.progress {
+            ui.add(egui::ProgressBar::new(progress).animate(true));
+            // Reporting a change makes the sketch update again, which picks up the worker's
+            // results.
+            ui.ctx().request_repaint();
+            return true;
+        }
+        false
+    }
+}
+
+register_widget_ui!(ExpansionStatus, ExpansionStatusWidget);
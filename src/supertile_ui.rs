@@ -0,0 +1,61 @@
+use whiskers::widgets::Widget;
+use whiskers::{prelude::*, register_widget_ui};
+
+use crate::render::{SupertileLayer, SupertileLayers};
+
+#[derive(Default)]
+pub struct SupertileLayersWidget {}
+
+impl Widget<SupertileLayers> for SupertileLayersWidget {
+    fn ui(&self, ui: &mut egui::Ui, label: &str, value: &mut SupertileLayers) -> bool {
+        let mut changed = false;
+        ui.label(label);
+        let mut removed = None;
+        egui::Grid::new(ui.id().with("supertile_layers"))
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("Show");
+                ui.label("Level");
+                ui.label("Width");
+                ui.end_row();
+                for (i, layer) in value.layers.iter_mut().enumerate() {
+                    changed |= ui.checkbox(&mut layer.enabled, "").changed();
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut layer.level).clamp_range(1..=30))
+                        .changed();
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut layer.line_thickness)
+                                .speed(0.01)
+                                .clamp_range(0.001..=20.0),
+                        )
+                        .changed();
+                    if ui.button("Remove").clicked() {
+                        removed = Some(i);
+                    }
+                    ui.end_row();
+                }
+            });
+        if let Some(i) = removed {
+            value.layers.remove(i);
+            changed = true;
+        }
+        if ui.button("Add supertile level").clicked() {
+            let level = value
+                .layers
+                .iter()
+                .map(|layer| layer.level)
+                .max()
+                .unwrap_or(0)
+                + 1;
+            value.layers.push(SupertileLayer {
+                level,
+                ..Default::default()
+            });
+            changed = true;
+        }
+        changed
+    }
+}
+
+register_widget_ui!(SupertileLayers, SupertileLayersWidget);
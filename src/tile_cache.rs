@@ -0,0 +1,411 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use kurbo::{Point, Rect};
+
+use crate::address::TileAddress;
+use crate::culling::{tile_intersects_rect, Culler};
+use crate::expansion::LevelOptions;
+use crate::layers::LayerPath;
+use crate::progress::Progress;
+use crate::render::*;
+use crate::tiling::*;
+
+impl TilingStep {
+    /// Hash of everything that affects the expansion, used to notice edits to the rules.
+    pub fn fingerprint(&self) -> u64 {
+        fn hash_point(p: &Point, state: &mut DefaultHasher) {
+            p.x.to_bits().hash(state);
+            p.y.to_bits().hash(state);
+        }
+        fn hash_placement(placement: &TilePlacement, state: &mut DefaultHasher) {
+            placement.tile_id.hash(state);
+            for x in placement.transform.as_coeffs() {
+                x.to_bits().hash(state);
+            }
+        }
+        let mut state = DefaultHasher::new();
+        self.expansion_factor.to_bits().hash(&mut state);
+        self.rules.len().hash(&mut state);
+        for rule in &self.rules {
+            rule.tile.corners.len().hash(&mut state);
+            for p in &rule.tile.corners {
+                hash_point(p, &mut state);
+            }
+            rule.tile.edges.len().hash(&mut state);
+            for edge in &rule.tile.edges {
+                match edge {
+                    EdgeShape::Straight => 0u8.hash(&mut state),
+                    EdgeShape::Quadratic(p) => {
+                        1u8.hash(&mut state);
+                        hash_point(p, &mut state);
+                    }
+                    EdgeShape::Cubic(p1, p2) => {
+                        2u8.hash(&mut state);
+                        hash_point(p1, &mut state);
+                        hash_point(p2, &mut state);
+                    }
+                    EdgeShape::Arc { bulge } => {
+                        3u8.hash(&mut state);
+                        bulge.to_bits().hash(&mut state);
+                    }
+                }
+            }
+            rule.result.len().hash(&mut state);
+            for placement in &rule.result {
+                hash_placement(placement, &mut state);
+            }
+        }
+        self.seed.len().hash(&mut state);
+        for placement in &self.seed {
+            hash_placement(placement, &mut state);
+        }
+        return state.finish();
+    }
+
+    /// Hash of what changes the drawing without changing the tiles: output layers and tile
+    /// decorations.
+    pub fn output_fingerprint(&self) -> u64 {
+        let mut state = DefaultHasher::new();
+        for rule in &self.rules {
+            rule.layer.hash(&mut state);
+            rule.tile.decorations.len().hash(&mut state);
+            for decoration in &rule.tile.decorations {
+                decoration.to_svg().hash(&mut state);
+            }
+        }
+        self.layers.len().hash(&mut state);
+        for layer in &self.layers {
+            layer.name.hash(&mut state);
+            layer.color.hash(&mut state);
+        }
+        return state.finish();
+    }
+
+    /// Expands only the tiles that intersect `bounds` but not `previous`, skipping every subtree
+    /// that lies completely inside `previous`. Merged by address with the tiles of an earlier
+    /// expansion into `previous`, this gives the same tiles in the same order as expanding into
+    /// `bounds` directly.
+    pub fn expand_exposed(
+        &self,
+        input: &[TilePlacement],
+        levels: usize,
+        bounds: Rect,
+        previous: Rect,
+        max_tiles: Option<usize>,
+        progress: &Progress,
+    ) -> Result<(Vec<(TilePlacement, TileAddress)>, ExpansionResult), TilingError> {
+        let culler = Culler::new(self, bounds);
+        let options = LevelOptions {
+            max_tiles,
+            parallel: false,
+            progress,
+        };
+        let expand = |tile: &TilePlacement| {
+            culler.subtree_visible(tile) && !culler.subtree_inside(tile, previous)
+        };
+        let (mut tiles, mut result) =
+            self.expand_with_addresses(input, levels, &options, expand, |_, _| {})?;
+        tiles.retain(|(tile, _)| {
+            culler.tile_visible(self, tile) && !tile_intersects_rect(self, tile, previous)
+        });
+        result.tiles = tiles.len();
+        return Ok((tiles, result));
+    }
+}
+
+/// Same tiles as `expand_tiles` along with their addresses, which let the cache merge newly
+/// exposed tiles in expansion order. The addresses are `None` when the levels don't fit in a
+/// `TileAddress`. `on_level` gets every completed level.
+pub fn expand_cached_tiles(
+    tiling: &TilingStep,
+    settings: &RenderSettings,
+    progress: &Progress,
+    on_level: impl FnMut(usize, &[(TilePlacement, TileAddress)]),
+) -> Result<(Vec<TilePlacement>, Option<Vec<TileAddress>>, ExpansionResult), TilingError> {
+    match expand_tiles_with_hierarchy(tiling, settings, progress, on_level) {
+        Ok((tiles, hierarchy, expansion)) => Ok((tiles, Some(hierarchy.addresses), expansion)),
+        Err(TilingError::AddressTooLong { .. }) => {
+            let (tiles, expansion) = expand_tiles(tiling, settings, progress)?;
+            Ok((tiles, None, expansion))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Everything that changes which tiles get generated, apart from the bounds.
+#[derive(Clone, PartialEq)]
+struct ExpansionKey {
+    fingerprint: u64,
+    levels: usize,
+    seed: Seed,
+    seed_scale: f64,
+    polygon_limit: usize,
+}
+
+impl ExpansionKey {
+    fn new(tiling: &TilingStep, settings: &RenderSettings) -> ExpansionKey {
+        ExpansionKey {
+            fingerprint: tiling.fingerprint(),
+            levels: settings.levels,
+            seed: settings.seed.clone(),
+            seed_scale: settings.seed_scale(tiling),
+            polygon_limit: settings.polygon_limit,
+        }
+    }
+}
+
+struct CachedTiles {
+    key: ExpansionKey,
+    bounds: Rect,
+    tiles: Vec<TilePlacement>,
+    /// Address of every tile, `None` when the expansion is too deep to address and can't be
+    /// extended.
+    addresses: Option<Vec<TileAddress>>,
+    expansion: ExpansionResult,
+}
+
+struct CachedOutput {
+    key: ExpansionKey,
+    settings: RenderSettings,
+    output_fingerprint: u64,
+    layers: Vec<LayerPath>,
+    expansion: ExpansionResult,
+    supertiles: Vec<SupertilePath>,
+}
+
+/// Keeps the expanded tiles and the finished path between sketch updates. Settings that only
+/// affect drawing reuse the path as is, panning re-expands just the newly exposed region.
+#[derive(Default)]
+pub struct TileCache {
+    tiles: Option<CachedTiles>,
+    output: Option<CachedOutput>,
+}
+
+/// Settings that don't change the generated path are reset, so that comparing the rest tells
+/// whether the cached output is still valid.
+fn output_settings(settings: &RenderSettings) -> RenderSettings {
+    RenderSettings {
+        line_thickness: 0.0,
+        depth_first: false,
+        parallel: false,
+        verbose: false,
+        ..settings.clone()
+    }
+}
+
+impl TileCache {
+    pub fn clear(&mut self) {
+        self.tiles = None;
+        self.output = None;
+    }
+
+    /// Whether `generate_path` can answer from the cache, either with the finished path or by
+    /// expanding just the newly exposed region.
+    pub fn can_reuse(&self, tiling: &TilingStep, settings: &RenderSettings) -> bool {
+        let key = ExpansionKey::new(tiling, settings);
+        let comparable = output_settings(settings);
+        if let Some(output) = &self.output {
+            if output.key == key
+                && output.settings == comparable
+                && output.output_fingerprint == tiling.output_fingerprint()
+            {
+                return true;
+            }
+        }
+        match &self.tiles {
+            Some(cached) => {
+                cached.key == key
+                    && !cached.expansion.truncated
+                    && (cached.bounds == settings.bounds() || cached.addresses.is_some())
+            }
+            None => false,
+        }
+    }
+
+    /// Stores tiles that were expanded elsewhere for `settings`, the next `generate_path` with
+    /// the same settings only converts them to a path.
+    pub fn insert_tiles(
+        &mut self,
+        tiling: &TilingStep,
+        settings: &RenderSettings,
+        tiles: Vec<TilePlacement>,
+        addresses: Option<Vec<TileAddress>>,
+        expansion: ExpansionResult,
+    ) {
+        self.output = None;
+        self.tiles = Some(CachedTiles {
+            key: ExpansionKey::new(tiling, settings),
+            bounds: settings.bounds(),
+            tiles,
+            addresses,
+            expansion,
+        });
+    }
+
+    /// Same result as `generate_path`, reusing as much of the previous call as possible. The
+    /// depth-first mode is meant to avoid keeping tiles in memory, so it bypasses the cache, as
+    /// does everything that needs tile addresses.
+    pub fn generate_path(
+        &mut self,
+        tiling: &TilingStep,
+        settings: &RenderSettings,
+        progress: &Progress,
+    ) -> Result<Rendered, TilingError> {
+        if settings.depth_first || settings.needs_hierarchy() || !settings.substitutes() {
+            self.clear();
+            return generate_path(tiling, settings, progress);
+        }
+        let key = ExpansionKey::new(tiling, settings);
+        let comparable = output_settings(settings);
+        let output_fingerprint = tiling.output_fingerprint();
+        if let Some(output) = &self.output {
+            if output.key == key
+                && output.settings == comparable
+                && output.output_fingerprint == output_fingerprint
+            {
+                return Ok(Rendered {
+                    layers: output.layers.clone(),
+                    expansion: output.expansion,
+                    supertiles: output.supertiles.clone(),
+                });
+            }
+        }
+        self.output = None;
+
+        self.update_tiles(tiling, settings, &key, progress)?;
+        let cached = self.tiles.as_ref().unwrap();
+        let layers = tile_layers(tiling, &cached.tiles, None, settings, progress)?;
+        let expansion = cached.expansion;
+        let supertiles = supertile_paths(tiling, settings, expansion.level_reached, progress)?;
+        self.output = Some(CachedOutput {
+            key,
+            settings: comparable,
+            output_fingerprint,
+            layers: layers.clone(),
+            expansion,
+            supertiles: supertiles.clone(),
+        });
+        return Ok(Rendered {
+            layers,
+            expansion,
+            supertiles,
+        });
+    }
+
+    fn update_tiles(
+        &mut self,
+        tiling: &TilingStep,
+        settings: &RenderSettings,
+        key: &ExpansionKey,
+        progress: &Progress,
+    ) -> Result<(), TilingError> {
+        let bounds = settings.bounds();
+        if let Some(cached) = &mut self.tiles {
+            // A truncated expansion stopped at some level, new tiles would not match it.
+            if cached.key == *key && !cached.expansion.truncated {
+                if cached.bounds == bounds {
+                    return Ok(());
+                }
+                match Self::extend(cached, tiling, settings, bounds, progress) {
+                    Ok(true) => return Ok(()),
+                    Ok(false) => {}
+                    // The kept tiles were already taken out of the cache.
+                    Err(err) => {
+                        self.tiles = None;
+                        return Err(err);
+                    }
+                }
+            }
+        }
+        self.tiles = None;
+        let (tiles, addresses, expansion) =
+            expand_cached_tiles(tiling, settings, progress, |_, _| {})?;
+        self.tiles = Some(CachedTiles {
+            key: key.clone(),
+            bounds,
+            tiles,
+            addresses,
+            expansion,
+        });
+        return Ok(());
+    }
+
+    /// Moves the cached tiles to new bounds, keeping them in expansion order. Returns false when
+    /// the polygon limit would be exceeded or the tiles have no addresses, the caller then
+    /// expands from scratch.
+    fn extend(
+        cached: &mut CachedTiles,
+        tiling: &TilingStep,
+        settings: &RenderSettings,
+        bounds: Rect,
+        progress: &Progress,
+    ) -> Result<bool, TilingError> {
+        let Some(addresses) = &mut cached.addresses else {
+            return Ok(false);
+        };
+        tiling.validate()?;
+        tiling.validate_seed(&settings.seed)?;
+        let mut tiles: Vec<(TilePlacement, TileAddress)> = cached
+            .tiles
+            .drain(..)
+            .zip(addresses.drain(..))
+            .filter(|(tile, _)| tile_intersects_rect(tiling, tile, bounds))
+            .collect();
+        let kept = tiles.len();
+        let input = tiling.seed_placements(&settings.seed, settings.seed_scale(tiling));
+        let limit = settings.polygon_limit.saturating_sub(kept);
+        let (mut exposed, result) = tiling.expand_exposed(
+            &input,
+            settings.levels,
+            bounds,
+            cached.bounds,
+            Some(limit),
+            progress,
+        )?;
+        if result.truncated || exposed.len() > limit {
+            return Ok(false);
+        }
+        // Both parts are already in expansion order, the stable sort just merges them.
+        tiles.append(&mut exposed);
+        tiles.sort_by(|a, b| a.1.cmp(&b.1));
+        (cached.tiles, *addresses) = tiles.into_iter().unzip();
+        cached.bounds = bounds;
+        cached.expansion.tiles = cached.tiles.len();
+        return Ok(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets;
+
+    #[test]
+    fn panning_keeps_expansion_order() {
+        let tiling = presets::load_preset("socolar_5").unwrap();
+        let mut settings = RenderSettings {
+            width: 20.0,
+            height: 20.0,
+            levels: 4,
+            parallel: false,
+            ..Default::default()
+        };
+        let mut cache = TileCache::default();
+        let progress = Progress::default();
+        let key = ExpansionKey::new(&tiling, &settings);
+        cache.update_tiles(&tiling, &settings, &key, &progress).unwrap();
+        for offset in [(5.0, 0.0), (5.0, 7.0), (-3.0, 2.0)] {
+            settings.offset = Point::new(offset.0, offset.1);
+            let cached = cache.tiles.as_mut().unwrap();
+            let bounds = settings.bounds();
+            assert!(TileCache::extend(cached, &tiling, &settings, bounds, &progress).unwrap());
+            let (fresh, _) = expand_tiles(&tiling, &settings, &progress).unwrap();
+            assert_eq!(cached.tiles.len(), fresh.len());
+            for (a, b) in cached.tiles.iter().zip(&fresh) {
+                assert_eq!(a.tile_id, b.tile_id);
+                assert_eq!(a.transform.as_coeffs(), b.transform.as_coeffs());
+            }
+        }
+    }
+}
@@ -1,8 +1,124 @@
-use kurbo::{Affine, BezPath, Point, Rect, Vec2};
+use std::fmt;
+
+use kurbo::{Affine, BezPath, CubicBez, ParamCurve, PathEl, Point, QuadBez, Rect, Shape, Vec2};
+
+use crate::culling::Culler;
+use crate::edge_matching::EdgeMatch;
+use crate::expansion::LevelOptions;
+use crate::layers::OutputLayer;
+use crate::progress::Progress;
+
+/// Tolerance for arcs and flattened outlines, in tile units.
+const CURVE_TOLERANCE: f64 = 1e-4;
+
+/// Shape of a tile edge in edge coordinates, where the edge runs from (0, 0) to (1, 0) and +y is
+/// a quarter turn counterclockwise from it. The same shape fits any edge and stays attached to
+/// the corners when they move.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum EdgeShape {
+    #[default]
+    Straight,
+    Quadratic(Point),
+    Cubic(Point, Point),
+    /// Circular arc whose middle lies `bulge` edge lengths off the edge, towards +y when
+    /// positive.
+    Arc { bulge: f64 },
+}
+
+impl EdgeShape {
+    /// Maps edge coordinates onto the edge from `a` to `b`.
+    pub fn edge_frame(a: Point, b: Point) -> Affine {
+        let d = b - a;
+        Affine::new([d.x, d.y, -d.y, d.x, a.x, a.y])
+    }
+
+    pub fn is_straight(&self) -> bool {
+        match self {
+            EdgeShape::Straight => true,
+            EdgeShape::Arc { bulge } => *bulge == 0.0,
+            _ => false,
+        }
+    }
+
+    pub fn is_finite(&self) -> bool {
+        let finite = |p: &Point| p.x.is_finite() && p.y.is_finite();
+        match self {
+            EdgeShape::Straight => true,
+            EdgeShape::Quadratic(p) => finite(p),
+            EdgeShape::Cubic(p1, p2) => finite(p1) && finite(p2),
+            EdgeShape::Arc { bulge } => bulge.is_finite(),
+        }
+    }
+
+    /// Point halfway along the curve, in edge coordinates.
+    pub fn midpoint(&self) -> Point {
+        let (start, end) = (Point::ZERO, Point::new(1.0, 0.0));
+        match self {
+            EdgeShape::Straight => Point::new(0.5, 0.0),
+            EdgeShape::Quadratic(p) => QuadBez::new(start, *p, end).eval(0.5),
+            EdgeShape::Cubic(p1, p2) => CubicBez::new(start, *p1, *p2, end).eval(0.5),
+            EdgeShape::Arc { bulge } => Point::new(0.5, *bulge),
+        }
+    }
+
+    /// Appends the edge, without the move to its start, mapped by `frame`, see `edge_frame`.
+    pub fn add_to_path(&self, out: &mut BezPath, frame: &Affine) {
+        let end = Point::new(1.0, 0.0);
+        match *self {
+            EdgeShape::Quadratic(p) => out.quad_to(*frame * p, *frame * end),
+            EdgeShape::Cubic(p1, p2) => out.curve_to(*frame * p1, *frame * p2, *frame * end),
+            EdgeShape::Arc { bulge } if bulge != 0.0 => {
+                // Circle through both ends and the point `bulge` off the middle of the edge.
+                let radius = (0.25 + bulge * bulge) / (2.0 * bulge.abs());
+                let center = Point::new(0.5, bulge - bulge.signum() * radius);
+                let half_angle = 0.5f64.atan2(radius - bulge.abs());
+                let arc = kurbo::Arc {
+                    center,
+                    radii: Vec2::new(radius, radius),
+                    start_angle: (-center.y).atan2(-center.x),
+                    sweep_angle: -2.0 * half_angle * bulge.signum(),
+                    x_rotation: 0.0,
+                };
+                out.extend(arc.append_iter(CURVE_TOLERANCE).map(|el| *frame * el));
+            }
+            _ => out.line_to(*frame * end),
+        }
+    }
+}
+
+/// How a matching rule marks an edge, relative to the edge's own direction from corner i to
+/// corner i + 1.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EdgeMarking {
+    #[default]
+    Plain,
+    /// Arrow pointing from corner i to corner i + 1.
+    Forward,
+    Backward,
+    /// Notch, meets an `Out` edge.
+    In,
+    Out,
+}
+
+/// Matching rule label of a tile edge. Neighbouring edges need the same `kind` and fitting
+/// markings, see `EdgeLabel::fits`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EdgeLabel {
+    /// 0 leaves the edge unconstrained.
+    pub kind: u32,
+    pub marking: EdgeMarking,
+}
 
 #[derive(Clone)]
 pub struct Tile {
     pub corners: Vec<Point>,
+    /// Shape of the edge from corner i to corner i + 1, missing entries are straight.
+    pub edges: Vec<EdgeShape>,
+    /// Matching rule label of every edge, missing entries are unconstrained.
+    pub labels: Vec<EdgeLabel>,
+    /// Open or closed curves in the tile's own frame, e.g. Ammann bars or Truchet arcs, drawn
+    /// with every placement of the tile.
+    pub decorations: Vec<BezPath>,
 }
 
 impl Tile {
@@ -20,31 +136,134 @@ impl Tile {
                 },
                 Point { x: dx, y: dy },
             ],
+            edges: Vec::new(),
+            labels: Vec::new(),
+            decorations: Vec::new(),
         }
     }
 
-    pub fn add_to_path(&self, out: &mut BezPath) {
-        if self.corners.is_empty() {
-            return;
+    pub fn edge(&self, index: usize) -> EdgeShape {
+        self.edges.get(index).copied().unwrap_or_default()
+    }
+
+    /// Adds straight edges up to `index` if needed.
+    pub fn edge_mut(&mut self, index: usize) -> &mut EdgeShape {
+        if self.edges.len() <= index {
+            self.edges.resize(index + 1, EdgeShape::Straight);
         }
-        out.move_to(self.corners[0]);
-        for p in self.corners.iter().skip(1) {
-            out.line_to(*p);
+        &mut self.edges[index]
+    }
+
+    pub fn label(&self, index: usize) -> EdgeLabel {
+        self.labels.get(index).copied().unwrap_or_default()
+    }
+
+    /// Adds unconstrained labels up to `index` if needed.
+    pub fn label_mut(&mut self, index: usize) -> &mut EdgeLabel {
+        if self.labels.len() <= index {
+            self.labels.resize(index + 1, EdgeLabel::default());
         }
-        out.close_path();
+        &mut self.labels[index]
+    }
+
+    pub fn is_polygon(&self) -> bool {
+        self.edges
+            .iter()
+            .take(self.corners.len())
+            .all(EdgeShape::is_straight)
+    }
+
+    pub fn add_to_path(&self, out: &mut BezPath) {
+        self.add_to_path_t(out, &Affine::IDENTITY);
     }
 
     pub fn add_to_path_t(&self, out: &mut BezPath, transform: &Affine) {
         if self.corners.is_empty() {
             return;
         }
+        let n = self.corners.len();
         out.move_to(*transform * self.corners[0]);
-        for p in self.corners.iter().skip(1) {
-            out.line_to(*transform * *p);
+        for i in 0..n {
+            let (a, b) = (self.corners[i], self.corners[(i + 1) % n]);
+            match self.edges.get(i) {
+                Some(edge) if !edge.is_straight() => {
+                    edge.add_to_path(out, &(*transform * EdgeShape::edge_frame(a, b)));
+                }
+                // The closing edge is drawn by `close_path`.
+                _ if i + 1 < n => out.line_to(*transform * b),
+                _ => {}
+            }
         }
         out.close_path();
     }
 
+    /// Corners and curve control points, the outline lies inside their convex hull.
+    pub fn control_points(&self) -> Vec<Point> {
+        if self.is_polygon() {
+            return self.corners.clone();
+        }
+        let mut result = Vec::new();
+        for el in self.to_path().elements() {
+            match *el {
+                PathEl::MoveTo(p) | PathEl::LineTo(p) => result.push(p),
+                PathEl::QuadTo(p1, p2) => result.extend([p1, p2]),
+                PathEl::CurveTo(p1, p2, p3) => result.extend([p1, p2, p3]),
+                PathEl::ClosePath => {}
+            }
+        }
+        return result;
+    }
+
+    /// The outline as a polygon, curved edges flattened.
+    pub fn outline_points(&self) -> Vec<Point> {
+        if self.is_polygon() {
+            return self.corners.clone();
+        }
+        let mut result = Vec::new();
+        self.to_path().flatten(CURVE_TOLERANCE, |el| match el {
+            PathEl::MoveTo(p) | PathEl::LineTo(p) => result.push(p),
+            _ => {}
+        });
+        if result.len() > 1 && result.first() == result.last() {
+            result.pop();
+        }
+        return result;
+    }
+
+    /// Corners and the middle of every curved edge, which is where matching curves meet.
+    pub fn snap_points(&self) -> Vec<Point> {
+        let n = self.corners.len();
+        let mut result = self.corners.clone();
+        for (i, edge) in self.edges.iter().take(n).enumerate() {
+            if !edge.is_straight() {
+                let frame = EdgeShape::edge_frame(self.corners[i], self.corners[(i + 1) % n]);
+                result.push(frame * edge.midpoint());
+            }
+        }
+        return result;
+    }
+
+    pub fn area(&self) -> f64 {
+        if !self.is_polygon() {
+            return self.to_path().area().abs();
+        }
+        let n = self.corners.len();
+        let twice_area: f64 = (0..n)
+            .map(|i| {
+                let a = self.corners[i];
+                let b = self.corners[(i + 1) % n];
+                a.x * b.y - b.x * a.y
+            })
+            .sum();
+        return (twice_area * 0.5).abs();
+    }
+
+    pub fn add_decorations_t(&self, out: &mut BezPath, transform: &Affine) {
+        for decoration in &self.decorations {
+            out.extend(decoration.elements().iter().map(|el| *transform * *el));
+        }
+    }
+
     pub fn to_path(&self) -> BezPath {
         let mut result = BezPath::new();
         self.add_to_path(&mut result);
@@ -60,110 +279,268 @@ pub struct TilePlacement {
 
 #[derive(Clone)]
 pub struct TilingRule {
+    pub name: String,
     pub tile: Tile,
     pub result: Vec<TilePlacement>,
+    /// Index into `TilingStep::layers` used when splitting the output by prototile.
+    pub layer: usize,
 }
 
+#[derive(Clone)]
 pub struct TilingStep {
     pub rules: Vec<TilingRule>,
     pub expansion_factor: f64,
+    /// Optional seed patch in prototile units, e.g. a vertex star, used instead of a single tile.
+    pub seed: Vec<TilePlacement>,
+    /// Output layers referenced by `TilingRule::layer`, missing entries get default names and
+    /// colours.
+    pub layers: Vec<OutputLayer>,
+    /// Edges that have to keep the same shape, see `set_edge_shape`.
+    pub edge_matches: Vec<EdgeMatch>,
 }
 
-const DEFAULT_POLYGON_LIMIT: usize = 1000000;
+#[derive(Clone, PartialEq)]
+pub struct Seed {
+    /// `None` uses `TilingStep::default_seed_tile`, the one the tiling was designed for.
+    pub tile_id: Option<usize>,
+    /// Start from `TilingStep::seed` instead of a single tile. Falls back to `tile_id` when the
+    /// tiling has no seed patch.
+    pub use_patch: bool,
+    /// Rotation in degrees.
+    pub rotation: f64,
+    pub position: Point,
+}
 
-impl TilingStep {
-    pub fn expand_tile(&self, placed_tile: &TilePlacement, output: &mut Vec<TilePlacement>) {
-        let rule = &self.rules[placed_tile.tile_id];
-        for item in &rule.result {
-            let mut new_tile = item.clone();
-            new_tile.transform = placed_tile.transform * new_tile.transform;
-            output.push(new_tile);
+impl Default for Seed {
+    fn default() -> Self {
+        Self {
+            tile_id: None,
+            use_patch: false,
+            rotation: 0.0,
+            position: Point::ZERO,
         }
     }
+}
+
+impl Seed {
+    pub fn transform(&self, scale: f64) -> Affine {
+        Affine::translate(self.position.to_vec2())
+            * Affine::rotate(self.rotation.to_radians())
+            * Affine::scale(scale)
+    }
+}
+
+pub const DEFAULT_POLYGON_LIMIT: usize = 1000000;
+
+/// Outcome of an expansion. When the tile limit is hit the expansion stops at the last level
+/// that fit completely, so the output never mixes levels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExpansionResult {
+    pub tiles: usize,
+    pub level_reached: usize,
+    pub truncated: bool,
+}
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum TilingError {
+    NoRules,
+    InvalidExpansionFactor(f64),
+    DegenerateTile { rule: usize },
+    NonFiniteValue { rule: usize },
+    InvalidTileId { rule: usize, child: usize, tile_id: usize },
+    InvalidPlacement { index: usize, tile_id: usize },
+    InvalidSeedTile(usize),
+    InvalidSeedPatchTile { index: usize, tile_id: usize },
+    AddressTooLong { levels: usize, bits: u32 },
+    InvalidEdgeMatch { index: usize, rule: usize, edge: usize },
+    /// The job was cancelled through its `Progress`.
+    Cancelled,
+    /// The multigrid needs a rhombus prototile with this corner angle in degrees.
+    MissingRhombus(f64),
+    /// The multigrid scale has to be a positive number.
+    InvalidMultigridScale(f64),
+    /// Multigrid rhombi share their sides, so every rhombus prototile needs the side of the
+    /// first one.
+    MismatchedRhombusSide { rule: usize, side: f64, expected: f64 },
+    /// Colouring by ancestor needs the address of every tile.
+    MissingAddresses,
+}
+
+impl fmt::Display for TilingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TilingError::NoRules => write!(f, "tiling has no rules"),
+            TilingError::InvalidExpansionFactor(value) => {
+                write!(f, "expansion_factor must be a positive number, got {value}")
+            }
+            TilingError::DegenerateTile { rule } => {
+                write!(f, "rule {rule}: tile needs at least 3 corners and a non-zero area")
+            }
+            TilingError::NonFiniteValue { rule } => {
+                write!(f, "rule {rule}: corners and transforms must be finite numbers")
+            }
+            TilingError::InvalidTileId {
+                rule,
+                child,
+                tile_id,
+            } => write!(
+                f,
+                "rule {rule}, child {child}: tile_id {tile_id} does not refer to an existing rule"
+            ),
+            TilingError::InvalidPlacement { index, tile_id } => write!(
+                f,
+                "placement {index}: tile_id {tile_id} does not refer to an existing rule"
+            ),
+            TilingError::InvalidSeedTile(tile_id) => {
+                write!(f, "seed tile {tile_id} does not refer to an existing rule")
+            }
+            TilingError::InvalidSeedPatchTile { index, tile_id } => write!(
+                f,
+                "seed patch tile {index}: tile_id {tile_id} does not refer to an existing rule"
+            ),
+            TilingError::AddressTooLong { levels, bits } => write!(
+                f,
+                "{levels} levels of {bits} bit child indices don't fit in a tile address"
+            ),
+            TilingError::InvalidEdgeMatch { index, rule, edge } => write!(
+                f,
+                "edge match {index}: rule {rule} has no edge {edge}"
+            ),
+            TilingError::Cancelled => write!(f, "cancelled"),
+            TilingError::MissingRhombus(degrees) => {
+                write!(f, "no prototile is a rhombus with a {degrees:.1}° corner")
+            }
+            TilingError::InvalidMultigridScale(value) => {
+                write!(f, "multigrid scale must be a positive number, got {value}")
+            }
+            TilingError::MismatchedRhombusSide {
+                rule,
+                side,
+                expected,
+            } => write!(
+                f,
+                "rule {rule}: rhombus side {side} differs from the first rhombus side {expected}"
+            ),
+            TilingError::MissingAddresses => write!(
+                f,
+                "colouring by ancestor needs tile addresses, which only substitution provides"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TilingError {}
+
+fn affine_is_finite(transform: &Affine) -> bool {
+    transform.as_coeffs().iter().all(|x| x.is_finite())
+}
+
+impl TilingStep {
     pub fn expand_levels(
         &self,
         input: &Vec<TilePlacement>,
         levels: usize,
         output: &mut Vec<TilePlacement>,
         max_tiles: Option<usize>,
-    ) {
-        let mut a = input.clone();
-        let mut b = Vec::new();
-        for _i in 0..levels {
-            for tile in &a {
-                self.expand_tile(&tile, &mut b);
-                if let Some(x) = max_tiles {
-                    if x < b.len() {
-                        break;
-                    }
-                }
-            }
-            std::mem::swap(&mut a, &mut b);
-            b.clear();
-        }
-        output.append(&mut a);
-    }
-
-    fn estimate_bounds(&self, placed_tile: &TilePlacement) -> Rect {
-        let tile = &self.rules[placed_tile.tile_id];
-        let mut result = Rect::from_origin_size(
-            placed_tile.transform.translation().to_point(),
-            (0.0_f64, 0.0_f64),
-        );
-        for p in &tile.tile.corners {
-            let p2 = placed_tile.transform * *p;
-            result = result.union_pt(p2);
-        }
-        let max_size = f64::max(result.width(), result.height());
-        return result.inflate(max_size, max_size);
+    ) -> ExpansionResult {
+        let (mut tiles, result) =
+            self.expand_placements(input, levels, max_tiles, false, |_| true);
+        output.append(&mut tiles);
+        return result;
     }
 
+    /// Only expands tiles that can affect `bounds`, and only outputs tiles that intersect it.
+    /// The serial reference that the culled expansions are tested against.
+    #[cfg(test)]
     pub fn expand_bound(
         &self,
         input: &Vec<TilePlacement>,
         levels: usize,
-        bounds: kurbo::Rect,
+        bounds: Rect,
         output: &mut Vec<TilePlacement>,
         max_tiles: Option<usize>,
-    ) {
-        let mut a = input.clone();
-        let mut b = Vec::new();
-        for _i in 0..levels {
-            for tile in &a {
-                let tile_bounds = self.estimate_bounds(tile);
-                if tile_bounds.intersect(bounds).is_empty() {
-                    continue;
-                }
-                self.expand_tile(&tile, &mut b);
-                if let Some(x) = max_tiles {
-                    if x < b.len() {
-                        break;
-                    }
-                }
-            }
-            std::mem::swap(&mut a, &mut b);
-            b.clear();
-        }
-        output.append(&mut a);
+    ) -> ExpansionResult {
+        let culler = Culler::new(self, bounds);
+        let (mut tiles, mut result) =
+            self.expand_placements(input, levels, max_tiles, false, |tile| {
+                culler.subtree_visible(tile)
+            });
+        tiles.retain(|tile| culler.tile_visible(self, tile));
+        result.tiles = tiles.len();
+        output.append(&mut tiles);
+        return result;
     }
 
-    pub fn expand_0_levels(
+    /// Like `expand_bound`/`expand_levels` but keeps every level: `levels[k]` holds the tiles
+    /// after `k` substitutions that intersect `bounds`, starting with the input itself. Fails
+    /// only when `progress` is cancelled.
+    pub fn expand_all_levels(
         &self,
+        input: &Vec<TilePlacement>,
         levels: usize,
-        initial_scale: f64,
         bounds: Option<Rect>,
-        output: &mut Vec<TilePlacement>,
-    ) {
-        let input = vec![TilePlacement {
-            tile_id: 1,
-            transform: Affine::scale(initial_scale),
-        }];
-        if let Some(bounds) = bounds {
-            self.expand_bound(&input, levels, bounds, output, Some(DEFAULT_POLYGON_LIMIT));
-        } else {
-            self.expand_levels(&input, levels, output, Some(DEFAULT_POLYGON_LIMIT));
+        max_tiles: Option<usize>,
+        progress: &Progress,
+    ) -> Result<(Vec<Vec<TilePlacement>>, ExpansionResult), TilingError> {
+        let culler = bounds.map(|bounds| Culler::new(self, bounds));
+        let options = LevelOptions {
+            max_tiles,
+            parallel: false,
+            progress,
+        };
+        let mut output: Vec<Vec<TilePlacement>> = Vec::with_capacity(levels + 1);
+        let expanded = self.expand_breadth_first(
+            input.iter().map(|tile| (tile.clone(), ())).collect(),
+            levels,
+            &options,
+            |tile| culler.as_ref().map_or(true, |culler| culler.subtree_visible(tile)),
+            |_, _| (),
+            |_, frontier| {
+                let visible = frontier.iter().map(|(tile, _)| tile).filter(|tile| {
+                    culler.as_ref().map_or(true, |culler| culler.tile_visible(self, tile))
+                });
+                output.push(visible.cloned().collect());
+            },
+        );
+        let (_, mut result) = expanded?;
+        result.tiles = output.last().map_or(0, |tiles| tiles.len());
+        return Ok((output, result));
+    }
+
+    /// Second prototile if there is one, which is what the built-in presets were designed for.
+    pub fn default_seed_tile(&self) -> usize {
+        1.min(self.rules.len().saturating_sub(1))
+    }
+
+    pub fn seed_tile(&self, seed: &Seed) -> usize {
+        seed.tile_id.unwrap_or_else(|| self.default_seed_tile())
+    }
+
+    /// `tile_id` if it refers to a rule, the default seed tile otherwise, e.g. after loading a
+    /// tiling with fewer rules.
+    pub fn clamp_seed_tile(&self, tile_id: usize) -> usize {
+        if tile_id < self.rules.len() {
+            return tile_id;
         }
+        return self.default_seed_tile();
+    }
+
+    pub fn seed_placements(&self, seed: &Seed, initial_scale: f64) -> Vec<TilePlacement> {
+        let transform = seed.transform(initial_scale);
+        if seed.use_patch && !self.seed.is_empty() {
+            return self
+                .seed
+                .iter()
+                .map(|placement| TilePlacement {
+                    tile_id: placement.tile_id,
+                    transform: transform * placement.transform,
+                })
+                .collect();
+        }
+        return vec![TilePlacement {
+            tile_id: self.seed_tile(seed),
+            transform,
+        }];
     }
 
     pub fn to_bez_path(&self, tiles: &Vec<TilePlacement>) -> BezPath {
@@ -175,39 +552,139 @@ impl TilingStep {
         return result;
     }
 
+    /// Checks everything the expansion functions index or divide by, so that the unchecked
+    /// versions can't panic afterwards.
+    pub fn validate(&self) -> Result<(), TilingError> {
+        if self.rules.is_empty() {
+            return Err(TilingError::NoRules);
+        }
+        if !(self.expansion_factor.is_finite() && self.expansion_factor > 0.0) {
+            return Err(TilingError::InvalidExpansionFactor(self.expansion_factor));
+        }
+        for (i, rule) in self.rules.iter().enumerate() {
+            if rule.tile.corners.iter().any(|p| !(p.x.is_finite() && p.y.is_finite()))
+                || rule.tile.edges.iter().any(|edge| !edge.is_finite())
+                || rule.result.iter().any(|x| !affine_is_finite(&x.transform))
+            {
+                return Err(TilingError::NonFiniteValue { rule: i });
+            }
+            if rule.tile.corners.len() < 3 || rule.tile.area() <= f64::EPSILON {
+                return Err(TilingError::DegenerateTile { rule: i });
+            }
+            for (j, placement) in rule.result.iter().enumerate() {
+                if placement.tile_id >= self.rules.len() {
+                    return Err(TilingError::InvalidTileId {
+                        rule: i,
+                        child: j,
+                        tile_id: placement.tile_id,
+                    });
+                }
+            }
+        }
+        for (index, placement) in self.seed.iter().enumerate() {
+            if placement.tile_id >= self.rules.len() {
+                return Err(TilingError::InvalidSeedPatchTile {
+                    index,
+                    tile_id: placement.tile_id,
+                });
+            }
+        }
+        self.validate_edge_matches()?;
+        return Ok(());
+    }
+
+    pub fn validate_seed(&self, seed: &Seed) -> Result<(), TilingError> {
+        let uses_patch = seed.use_patch && !self.seed.is_empty();
+        let tile_id = self.seed_tile(seed);
+        if !uses_patch && tile_id >= self.rules.len() {
+            return Err(TilingError::InvalidSeedTile(tile_id));
+        }
+        return Ok(());
+    }
+
+    pub fn validate_placements(&self, tiles: &[TilePlacement]) -> Result<(), TilingError> {
+        for (index, tile) in tiles.iter().enumerate() {
+            if tile.tile_id >= self.rules.len() {
+                return Err(TilingError::InvalidPlacement {
+                    index,
+                    tile_id: tile.tile_id,
+                });
+            }
+        }
+        return Ok(());
+    }
+
+    pub fn try_expand_levels(
+        &self,
+        input: &Vec<TilePlacement>,
+        levels: usize,
+        output: &mut Vec<TilePlacement>,
+        max_tiles: Option<usize>,
+    ) -> Result<ExpansionResult, TilingError> {
+        self.validate()?;
+        self.validate_placements(input)?;
+        return Ok(self.expand_levels(input, levels, output, max_tiles));
+    }
+
     pub fn new() -> TilingStep {
         TilingStep {
             rules: Vec::new(),
             expansion_factor: 1.0,
+            seed: Vec::new(),
+            layers: Vec::new(),
+            edge_matches: Vec::new(),
         }
     }
 
     pub fn snap_targets(&self, rule: usize, excluded_shapes: &Vec<usize>) -> Vec<kurbo::Point> {
-        let mut result = Vec::new();
         if let Some(rule) = self.rules.get(rule) {
-            result.extend(&rule.tile.corners);
-            for (i, placement) in rule.result.iter().enumerate() {
-                if excluded_shapes.contains(&i) {
-                    continue;
-                }
-                let other_tile = &self.rules[placement.tile_id];
-                for p in &other_tile.tile.corners {
-                    result.push(placement.transform * *p);
-                }
+            return self.patch_snap_targets(Some(&rule.tile), &rule.result, excluded_shapes);
+        }
+        return Vec::new();
+    }
+
+    pub fn patch_snap_targets(
+        &self,
+        outline: Option<&Tile>,
+        placements: &[TilePlacement],
+        excluded_shapes: &Vec<usize>,
+    ) -> Vec<kurbo::Point> {
+        let mut result = Vec::new();
+        if let Some(outline) = outline {
+            result.extend(outline.snap_points());
+        }
+        for (i, placement) in placements.iter().enumerate() {
+            if excluded_shapes.contains(&i) {
+                continue;
+            }
+            let Some(other_tile) = self.rules.get(placement.tile_id) else {
+                continue;
+            };
+            for p in other_tile.tile.snap_points() {
+                result.push(placement.transform * p);
             }
         }
         return result;
     }
 
     pub fn rule_points(&self, rule: usize, shapes: &Vec<usize>) -> Vec<kurbo::Point> {
-        let mut result = Vec::new();
         if let Some(rule) = self.rules.get(rule) {
-            for id in shapes {
-                let placement = &rule.result[*id];
-                let other_tile = &self.rules[placement.tile_id];
-                for p in &other_tile.tile.corners {
-                    result.push(placement.transform * *p);
-                }
+            return self.patch_points(&rule.result, shapes);
+        }
+        return Vec::new();
+    }
+
+    pub fn patch_points(&self, placements: &[TilePlacement], shapes: &Vec<usize>) -> Vec<kurbo::Point> {
+        let mut result = Vec::new();
+        for id in shapes {
+            let Some(placement) = placements.get(*id) else {
+                continue;
+            };
+            let Some(other_tile) = self.rules.get(placement.tile_id) else {
+                continue;
+            };
+            for p in other_tile.tile.snap_points() {
+                result.push(placement.transform * p);
             }
         }
         return result;
@@ -0,0 +1,193 @@
+use crate::tiling::*;
+
+const POWER_ITERATIONS: usize = 1000;
+const POWER_EPSILON: f64 = 1e-13;
+const FACTOR_TOLERANCE: f64 = 1e-6;
+
+pub struct SubstitutionAnalysis {
+    /// `matrix[i][j]` is the number of tiles with id `i` produced by rule `j`.
+    pub matrix: Vec<Vec<usize>>,
+    pub primitive: bool,
+    /// Perron-Frobenius eigenvalue, the factor by which the area grows per step.
+    pub eigenvalue: f64,
+    pub inflation_factor: f64,
+    /// Right eigenvector normalized to sum 1: relative number of each tile in a large patch.
+    pub frequencies: Vec<f64>,
+    /// Left eigenvector normalized so that the largest tile has area 1.
+    pub relative_areas: Vec<f64>,
+    /// Actual polygon areas with the same normalization as `relative_areas`.
+    pub polygon_areas: Vec<f64>,
+}
+
+impl SubstitutionAnalysis {
+    /// Why `expansion_factor` doesn't fit the substitution matrix, if it doesn't.
+    pub fn expansion_factor_warning(&self, expansion_factor: f64) -> Option<String> {
+        if !self.primitive {
+            return Some(String::from(
+                "substitution matrix is not primitive, the inflation factor is not well defined",
+            ));
+        }
+        let expected = self.inflation_factor;
+        if (expansion_factor - expected).abs() > FACTOR_TOLERANCE * expected {
+            return Some(format!(
+                "expansion_factor {} doesn't match the inflation factor {} of the substitution matrix",
+                expansion_factor, expected
+            ));
+        }
+        return None;
+    }
+}
+
+pub fn substitution_matrix(step: &TilingStep) -> Vec<Vec<usize>> {
+    let n = step.rules.len();
+    let mut matrix = vec![vec![0; n]; n];
+    for (j, rule) in step.rules.iter().enumerate() {
+        for placement in &rule.result {
+            if placement.tile_id < n {
+                matrix[placement.tile_id][j] += 1;
+            }
+        }
+    }
+    return matrix;
+}
+
+/// A non-negative matrix is primitive if some power of it is strictly positive. By Wielandt's
+/// theorem it is enough to check the power `(n-1)^2 + 1`.
+pub fn is_primitive(matrix: &[Vec<usize>]) -> bool {
+    let n = matrix.len();
+    if n == 0 {
+        return false;
+    }
+    let pattern: Vec<Vec<bool>> = matrix
+        .iter()
+        .map(|row| row.iter().map(|x| *x > 0).collect())
+        .collect();
+    let mut power = pattern.clone();
+    for _ in 1..((n - 1) * (n - 1) + 1) {
+        if power.iter().flatten().all(|x| *x) {
+            return true;
+        }
+        let mut next = vec![vec![false; n]; n];
+        for i in 0..n {
+            for j in 0..n {
+                next[i][j] = (0..n).any(|k| power[i][k] && pattern[k][j]);
+            }
+        }
+        power = next;
+    }
+    return power.iter().flatten().all(|x| *x);
+}
+
+/// Power iteration on `M + I`, which has the same eigenvectors but doesn't oscillate for
+/// periodic matrices.
+fn dominant_eigenvector(matrix: &[Vec<f64>]) -> (f64, Vec<f64>) {
+    let n = matrix.len();
+    let mut v = vec![1.0 / n as f64; n];
+    let mut eigenvalue = 0.0;
+    for _ in 0..POWER_ITERATIONS {
+        let mut next: Vec<f64> = (0..n)
+            .map(|i| v[i] + (0..n).map(|j| matrix[i][j] * v[j]).sum::<f64>())
+            .collect();
+        let sum: f64 = next.iter().sum();
+        if sum <= 0.0 {
+            break;
+        }
+        next.iter_mut().for_each(|x| *x /= sum);
+        let change: f64 = next.iter().zip(&v).map(|(a, b)| (a - b).abs()).sum();
+        eigenvalue = sum - 1.0;
+        v = next;
+        if change < POWER_EPSILON {
+            break;
+        }
+    }
+    return (eigenvalue, v);
+}
+
+fn normalize_max(values: &mut [f64]) {
+    let max = values.iter().cloned().fold(0.0, f64::max);
+    if max > 0.0 {
+        values.iter_mut().for_each(|x| *x /= max);
+    }
+}
+
+impl TilingStep {
+    pub fn analyze(&self) -> Option<SubstitutionAnalysis> {
+        let n = self.rules.len();
+        if n == 0 {
+            return None;
+        }
+        let matrix = substitution_matrix(self);
+        let as_float: Vec<Vec<f64>> = matrix
+            .iter()
+            .map(|row| row.iter().map(|x| *x as f64).collect())
+            .collect();
+        let transposed: Vec<Vec<f64>> = (0..n)
+            .map(|i| (0..n).map(|j| as_float[j][i]).collect())
+            .collect();
+        let (eigenvalue, frequencies) = dominant_eigenvector(&as_float);
+        let (_, mut relative_areas) = dominant_eigenvector(&transposed);
+        normalize_max(&mut relative_areas);
+        let mut polygon_areas: Vec<f64> = self.rules.iter().map(|x| x.tile.area()).collect();
+        normalize_max(&mut polygon_areas);
+
+        Some(SubstitutionAnalysis {
+            primitive: is_primitive(&matrix),
+            matrix,
+            eigenvalue,
+            inflation_factor: eigenvalue.max(0.0).sqrt(),
+            frequencies,
+            relative_areas,
+            polygon_areas,
+        })
+    }
+
+    pub fn expansion_factor_warning(&self) -> Option<String> {
+        let analysis = self.analyze()?;
+        return analysis.expansion_factor_warning(self.expansion_factor);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets;
+
+    const TOLERANCE: f64 = 1e-6;
+
+    #[test]
+    fn penrose_inflation() {
+        let tiling = presets::load_preset("socolar_5").unwrap();
+        let analysis = tiling.analyze().unwrap();
+        let golden = (1.0 + 5f64.sqrt()) / 2.0;
+        assert!(analysis.primitive);
+        assert!((analysis.eigenvalue - golden.powi(4)).abs() < TOLERANCE);
+        assert!((analysis.inflation_factor - golden.powi(2)).abs() < TOLERANCE);
+        assert!((analysis.frequencies.iter().sum::<f64>() - 1.0).abs() < TOLERANCE);
+        assert!(tiling.expansion_factor_warning().is_none());
+    }
+
+    #[test]
+    fn square_grid_doubles() {
+        let tiling = presets::load_preset("square_grid").unwrap();
+        let analysis = tiling.analyze().unwrap();
+        assert_eq!(analysis.matrix, vec![vec![4]]);
+        assert!((analysis.eigenvalue - 4.0).abs() < TOLERANCE);
+        assert!((analysis.inflation_factor - 2.0).abs() < TOLERANCE);
+        assert!(analysis.expansion_factor_warning(3.0).is_some());
+    }
+
+    #[test]
+    fn reducible_matrix_is_not_primitive() {
+        // The second tile never produces the first.
+        assert!(!is_primitive(&[vec![1, 1], vec![0, 1]]));
+        assert!(is_primitive(&[vec![0, 1], vec![1, 1]]));
+        assert!(!is_primitive(&[]));
+        let mut tiling = presets::load_preset("socolar_5").unwrap();
+        for item in &mut tiling.rules[1].result {
+            item.tile_id = 1;
+        }
+        let analysis = tiling.analyze().unwrap();
+        assert!(!analysis.primitive);
+        assert!(tiling.expansion_factor_warning().is_some());
+    }
+}
@@ -3,9 +3,12 @@ use std::sync::{Arc, Mutex};
 use std::thread::current;
 use std::vec;
 
+use crate::edge_matching::{EdgeMatch, EdgeRef};
+use crate::matching_rules::LabelViolation;
 use crate::tiling::*;
+use crate::tiling_validation::RuleValidation;
 use egui::{emath, Id, Rect};
-use kurbo::{Affine, BezPath, Point, Shape};
+use kurbo::{Affine, BezPath, PathEl, Point, Shape};
 use whiskers::prelude::egui::emath::RectTransform;
 use whiskers::prelude::egui::epaint::PathShape;
 use whiskers::prelude::egui::{epaint, Color32, Painter, Pos2, Response, Sense, Stroke, Vec2};
@@ -39,6 +42,37 @@ struct WindowState {
     drag_activated: bool,
     snap: bool,
     last_snap_pint:Option<Pos2>,
+    file_path: String,
+    file_status: String,
+    editing_seed: bool,
+    new_tile_id: usize,
+    /// Rule whose decorations are in `decoration_text`, one SVG path per line.
+    decoration_rule: Option<usize>,
+    decoration_text: String,
+    decoration_error: String,
+    new_match: EdgeMatch,
+    label_check_levels: usize,
+    label_check: Option<LabelCheck>,
+    validation: Option<CachedValidation>,
+}
+
+/// Coverage check of a rule, redone when the tile or the rules change.
+struct CachedValidation {
+    tile: usize,
+    /// `TilingStep::fingerprint` of the checked rules.
+    fingerprint: u64,
+    validation: Option<RuleValidation>,
+}
+
+/// Result of checking the edge labels on a patch expanded from the edited rule or seed.
+struct LabelCheck {
+    editing_seed: bool,
+    tile: usize,
+    levels: usize,
+    tiles: usize,
+    /// Tile ids of the tiles of every violation.
+    tile_ids: Vec<(usize, usize)>,
+    violations: Vec<LabelViolation>,
 }
 
 impl Default for WindowState {
@@ -54,6 +88,20 @@ impl Default for WindowState {
             drag_activated: false,
             snap: true,
             last_snap_pint: None,
+            file_path: String::from("tiling.ron"),
+            file_status: String::new(),
+            editing_seed: false,
+            new_tile_id: 0,
+            decoration_rule: None,
+            decoration_text: String::new(),
+            decoration_error: String::new(),
+            new_match: EdgeMatch {
+                reversed: true,
+                ..Default::default()
+            },
+            label_check_levels: 3,
+            label_check: None,
+            validation: None,
         }
     }
 }
@@ -90,6 +138,98 @@ fn as_points(tile: &Tile, placement: &Affine, tr: &RectTransform) -> Vec<Pos2> {
         .collect()
 }
 
+/// Flattened decorations of a tile as screen space polylines.
+fn decoration_lines(tile: &Tile, placement: &Affine, tr: &RectTransform) -> Vec<Vec<Pos2>> {
+    let mut lines: Vec<Vec<Pos2>> = Vec::new();
+    for decoration in &tile.decorations {
+        let mut start = None;
+        (*placement * decoration.clone()).flatten(DECORATION_TOLERANCE, |el| match el {
+            PathEl::MoveTo(p) => {
+                start = Some(p);
+                lines.push(vec![tr * to_pos(p)]);
+            }
+            PathEl::LineTo(p) => {
+                if let Some(line) = lines.last_mut() {
+                    line.push(tr * to_pos(p));
+                }
+            }
+            PathEl::ClosePath => {
+                if let (Some(line), Some(p)) = (lines.last_mut(), start) {
+                    line.push(tr * to_pos(p));
+                }
+            }
+            _ => {}
+        });
+    }
+    return lines;
+}
+
+/// Outline with curved edges flattened, for drawing.
+fn as_outline(tile: &Tile, placement: &Affine, tr: &RectTransform) -> Vec<Pos2> {
+    tile.outline_points()
+        .into_iter()
+        .map(|p| tr * to_pos(*placement * p))
+        .collect()
+}
+
+/// Draggable points of an edge in edge coordinates, the middle of an arc stands in for its
+/// bulge.
+fn edge_controls(edge: &EdgeShape) -> Vec<Point> {
+    match *edge {
+        EdgeShape::Straight => Vec::new(),
+        EdgeShape::Quadratic(p) => vec![p],
+        EdgeShape::Cubic(p1, p2) => vec![p1, p2],
+        EdgeShape::Arc { bulge } => vec![Point::new(0.5, bulge)],
+    }
+}
+
+fn move_edge_control(edge: &EdgeShape, index: usize, p: Point) -> EdgeShape {
+    match *edge {
+        EdgeShape::Quadratic(_) => EdgeShape::Quadratic(p),
+        EdgeShape::Cubic(p1, _) if index == 1 => EdgeShape::Cubic(p1, p),
+        EdgeShape::Cubic(_, p2) => EdgeShape::Cubic(p, p2),
+        EdgeShape::Arc { .. } => EdgeShape::Arc { bulge: p.y },
+        EdgeShape::Straight => EdgeShape::Straight,
+    }
+}
+
+/// Marks every labelled edge in its middle: arrows, a tick towards the inside or outside of the
+/// tile for notches, a dot for plain edges, with the label kind next to it.
+fn draw_labels(painter: &Painter, tile: &Tile, placement: &Affine, tr: &RectTransform) {
+    let n = tile.corners.len();
+    let winding = if tile.to_path().area() >= 0.0 { 1.0 } else { -1.0 };
+    for i in 0..n {
+        let label = tile.label(i);
+        if label.kind == 0 {
+            continue;
+        }
+        let frame = EdgeShape::edge_frame(tile.corners[i], tile.corners[(i + 1) % n]);
+        let middle = tile.edge(i).midpoint();
+        let screen = |p: Point| tr * to_pos(*placement * (frame * p));
+        let center = screen(middle);
+        let towards = |p: Point| (screen(p) - center).normalized() * LABEL_SIZE;
+        let along = towards(middle + kurbo::Vec2::new(0.1, 0.0));
+        // +y in edge coordinates is inside for counterclockwise tiles.
+        let inside = towards(middle + kurbo::Vec2::new(0.0, 0.1 * winding));
+        let color = LABEL_COLORS[label.kind as usize % LABEL_COLORS.len()];
+        let stroke = Stroke::new(2.0, color);
+        match label.marking {
+            EdgeMarking::Plain => painter.circle_filled(center, 3.0, color),
+            EdgeMarking::Forward => painter.arrow(center - along * 0.5, along, stroke),
+            EdgeMarking::Backward => painter.arrow(center + along * 0.5, -along, stroke),
+            EdgeMarking::In => painter.line_segment([center, center + inside], stroke),
+            EdgeMarking::Out => painter.line_segment([center, center - inside], stroke),
+        }
+        painter.text(
+            center + inside * 1.5,
+            egui::Align2::CENTER_CENTER,
+            label.kind.to_string(),
+            egui::FontId::proportional(LABEL_SIZE),
+            color,
+        );
+    }
+}
+
 fn rough_bounds(path: &BezPath, transform: &RectTransform) -> Rect {
     let bbox = path.bounding_box();
     let mut res = Rect::NOTHING;
@@ -101,8 +241,40 @@ fn rough_bounds(path: &BezPath, transform: &RectTransform) -> Rect {
 
 const DRAG_START: f64 = 5.0;
 const SNAP_DISTANCE: f64 = 0.04;
+const VALIDATION_TOLERANCE: f64 = 0.01;
+const DECORATION_TOLERANCE: f64 = 0.005;
+const DECORATION_COLOR: Color32 = Color32::from_rgb(200, 120, 0);
+const LABEL_COLORS: [Color32; 6] = [
+    Color32::from_rgb(220, 40, 40),
+    Color32::from_rgb(30, 90, 220),
+    Color32::from_rgb(20, 150, 60),
+    Color32::from_rgb(140, 50, 180),
+    Color32::from_rgb(0, 160, 170),
+    Color32::from_rgb(120, 80, 40),
+];
+/// Screen size of the edge label markers.
+const LABEL_SIZE: f32 = 10.0;
+const LABEL_CHECK_TILE_LIMIT: usize = 100000;
+const LABEL_CHECK_TOLERANCE: f64 = 1e-6;
 
 impl WindowState {
+    fn edited_placements<'a>(&self, value: &'a TilingStep) -> Option<&'a Vec<TilePlacement>> {
+        if self.editing_seed {
+            return Some(&value.seed);
+        }
+        value.rules.get(self.current_tile).map(|rule| &rule.result)
+    }
+
+    fn edited_placements_mut<'a>(
+        &self,
+        value: &'a mut TilingStep,
+    ) -> Option<&'a mut Vec<TilePlacement>> {
+        if self.editing_seed {
+            return Some(&mut value.seed);
+        }
+        value.rules.get_mut(self.current_tile).map(|rule| &mut rule.result)
+    }
+
     fn display_shapes(
         &mut self,
         ui: &mut egui::Ui,
@@ -111,14 +283,23 @@ impl WindowState {
     ) {
         let mut clicked_something = false;
 
-        let current_rule = value.rules[self.current_tile].clone();
+        let Some(placements) = self.edited_placements(value).cloned() else {
+            return;
+        };
+        let outline = if self.editing_seed {
+            None
+        } else {
+            value.rules.get(self.current_tile).map(|rule| rule.tile.clone())
+        };
         let mouse_pos = ui
             .input(|inp| inp.pointer.hover_pos())
             .unwrap_or(Pos2::new(0.0, 0.0));
         let draw_mouse_pos = to_point(self.draw_transform.inverse().transform_pos(mouse_pos));
 
-        for (j, shape) in current_rule.result.iter().enumerate() {
-            let tile = &value.rules[shape.tile_id].tile;
+        for (j, shape) in placements.iter().enumerate() {
+            let Some(tile) = value.rules.get(shape.tile_id).map(|rule| &rule.tile) else {
+                continue;
+            };
 
             let points = as_points(tile, &shape.transform, &self.draw_transform);
             for (i, p) in points.iter().enumerate() {
@@ -193,14 +374,21 @@ impl WindowState {
                 _ => {}
             }
 
-            let shape = egui::Shape::closed_line(points, stroke);
-            ui.painter().add(shape);
+            for line in decoration_lines(tile, &shape.transform, &self.draw_transform) {
+                painter.add(egui::Shape::line(line, Stroke::new(1.0, DECORATION_COLOR)));
+            }
+
+            let outline = as_outline(tile, &shape.transform, &self.draw_transform);
+            ui.painter().add(egui::Shape::closed_line(outline, stroke));
+            draw_labels(painter, tile, &shape.transform, &self.draw_transform);
         }
 
-        for (j, shape) in current_rule.result.iter().enumerate() {
-            let tile = &value.rules[shape.tile_id].tile;
+        for (j, shape) in placements.iter().enumerate() {
+            let Some(tile) = value.rules.get(shape.tile_id).map(|rule| &rule.tile) else {
+                continue;
+            };
             let id = response.id.with("subtile").with(j);
-            let positioned_tile = shape.transform * value.rules[shape.tile_id].tile.to_path();
+            let positioned_tile = shape.transform * tile.to_path();
             let hovered = positioned_tile.contains(draw_mouse_pos);
             let resp = ui.interact_with_hovered(
                 rough_bounds(&positioned_tile, &self.draw_transform),
@@ -228,8 +416,7 @@ impl WindowState {
                 if maybe_drag {
                     if let Selection::Shapes { shapes } = &self.selection {
                         for shape in shapes {
-                            self.drag_transforms
-                                .push(current_rule.result[*shape].transform);
+                            self.drag_transforms.push(placements[*shape].transform);
                         }
                         self.drag_start_p = resp.interact_pointer_pos().unwrap_or_default();
                     }
@@ -245,16 +432,19 @@ impl WindowState {
                         transform.transform_pos(p2) - transform.transform_pos(self.drag_start_p);
                     if self.drag_activated || mouse_movement.length() > DRAG_START as f32 {
                         self.drag_activated = true;
-                        let current_rule = &mut value.rules[self.current_tile];
-                        for (i, shape) in shapes.iter().enumerate() {
-                            current_rule.result[*shape].transform =
-                                self.drag_transforms[i].then_translate(to_tile_vec(movement_draw));
+                        if let Some(edited) = self.edited_placements_mut(value) {
+                            for (i, shape) in shapes.iter().enumerate() {
+                                edited[*shape].transform = self.drag_transforms[i]
+                                    .then_translate(to_tile_vec(movement_draw));
+                            }
                         }
                     }
 
                     if self.snap && !shift {
-                        let snap_points = value.snap_targets(self.current_tile, shapes);
-                        let movable_points = value.rule_points(self.current_tile, shapes);
+                        let edited = self.edited_placements(value).cloned().unwrap_or_default();
+                        let snap_points =
+                            value.patch_snap_targets(outline.as_ref(), &edited, shapes);
+                        let movable_points = value.patch_points(&edited, shapes);
                         let mut best: Option<(Point, Point)> = None;
                         let mut best_distance = 0f64;
                         for targets in &snap_points {
@@ -277,13 +467,12 @@ impl WindowState {
                                 Color32::TRANSPARENT,
                                 Stroke::new(1.0, Color32::BLACK),
                             );
-                            let current_rule = &mut value.rules[self.current_tile];
                             let movement = t - f;
-                            for shape in shapes.iter() {
-                                current_rule.result[*shape].transform = current_rule.result
-                                    [*shape]
-                                    .transform
-                                    .then_translate(movement);
+                            if let Some(edited) = self.edited_placements_mut(value) {
+                                for shape in shapes.iter() {
+                                    edited[*shape].transform =
+                                        edited[*shape].transform.then_translate(movement);
+                                }
                             }
                         }
                     }
@@ -296,6 +485,234 @@ impl WindowState {
         }
     }
 
+    /// Decorations of the current rule as SVG path data, applied once every line parses.
+    fn decorations_ui(&mut self, ui: &mut egui::Ui, value: &mut TilingStep) {
+        let Some(rule) = value.rules.get_mut(self.current_tile) else {
+            return;
+        };
+        if self.decoration_rule != Some(self.current_tile) {
+            self.decoration_rule = Some(self.current_tile);
+            self.decoration_text = rule
+                .tile
+                .decorations
+                .iter()
+                .map(BezPath::to_svg)
+                .collect::<Vec<_>>()
+                .join("\n");
+            self.decoration_error.clear();
+        }
+        ui.label("Decorations");
+        let response = ui.add(
+            egui::TextEdit::multiline(&mut self.decoration_text)
+                .desired_rows(3)
+                .hint_text("M 0 0 L 0.5 0.5"),
+        );
+        if response.changed() {
+            let parsed: Result<Vec<BezPath>, _> = self
+                .decoration_text
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(BezPath::from_svg)
+                .collect();
+            match parsed {
+                Ok(decorations) => {
+                    rule.tile.decorations = decorations;
+                    self.decoration_error.clear();
+                }
+                Err(err) => self.decoration_error = err.to_string(),
+            }
+        }
+        if !self.decoration_error.is_empty() {
+            ui.colored_label(Color32::RED, &self.decoration_error);
+        }
+    }
+
+    /// Handles on the control points of the edited tile's curved edges. Dragging one reshapes
+    /// the edge together with every edge matched to it.
+    fn edge_handles(
+        &mut self,
+        ui: &mut egui::Ui,
+        value: &mut TilingStep,
+        (response, painter): &(Response, Painter),
+    ) {
+        if self.editing_seed {
+            return;
+        }
+        let rule = self.current_tile;
+        let Some(tile) = value.rules.get(rule).map(|rule| rule.tile.clone()) else {
+            return;
+        };
+        let n = tile.corners.len();
+        for i in 0..n {
+            let edge = tile.edge(i);
+            let frame = EdgeShape::edge_frame(tile.corners[i], tile.corners[(i + 1) % n]);
+            let ends = [Point::ZERO, Point::new(1.0, 0.0)];
+            let controls = edge_controls(&edge);
+            for (k, control) in controls.iter().enumerate() {
+                let p = self.draw_transform * to_pos(frame * *control);
+                if !matches!(edge, EdgeShape::Arc { .. }) {
+                    // Quadratic handles hang off the start, cubic ones off the nearer end.
+                    let end = self.draw_transform * to_pos(frame * ends[k.min(1)]);
+                    painter.line_segment([end, p], Stroke::new(1.0, Color32::GRAY));
+                }
+                let handle = ui.interact(
+                    Rect::from_center_size(p, egui::Vec2::new(10.0, 10.0)),
+                    response.id.with("edge_control").with(i).with(k),
+                    Sense::drag(),
+                );
+                let color = if handle.hovered() || handle.dragged() {
+                    Color32::GREEN
+                } else {
+                    DECORATION_COLOR
+                };
+                painter.circle_filled(p, 4.0, color);
+                if handle.dragged() {
+                    if let Some(pos) = handle.interact_pointer_pos() {
+                        let local = to_point(self.draw_transform.inverse().transform_pos(pos));
+                        let shape = move_edge_control(&edge, k, frame.inverse() * local);
+                        value.set_edge_shape(EdgeRef { rule, edge: i }, shape);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Pairs of edges that keep the same shape, flagged when they currently differ.
+    fn edge_matches_ui(&mut self, ui: &mut egui::Ui, value: &mut TilingStep) {
+        egui::CollapsingHeader::new("Matched edges").show(ui, |ui| {
+            let mut removed = None;
+            for (i, matched) in value.edge_matches.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    let reversed = if matched.reversed { " reversed" } else { "" };
+                    let text = format!(
+                        "{}:{} - {}:{}{reversed}",
+                        matched.a.rule, matched.a.edge, matched.b.rule, matched.b.edge
+                    );
+                    if value.edge_match_holds(matched) {
+                        ui.label(text);
+                    } else {
+                        ui.colored_label(Color32::RED, text);
+                    }
+                    if ui.button("Remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = removed {
+                value.edge_matches.remove(i);
+            }
+
+            let new_match = &mut self.new_match;
+            ui.horizontal(|ui| {
+                for edge in [&mut new_match.a, &mut new_match.b] {
+                    ui.add(egui::DragValue::new(&mut edge.rule).prefix("tile "));
+                    ui.add(egui::DragValue::new(&mut edge.edge).prefix("edge "));
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut new_match.reversed, "Reversed");
+                let valid = value.edge_shape(new_match.a).is_some()
+                    && value.edge_shape(new_match.b).is_some();
+                if ui.add_enabled(valid, egui::Button::new("Match")).clicked() {
+                    value.edge_matches.push(*new_match);
+                    // The new partner takes the shape of the first edge.
+                    if let Some(shape) = value.edge_shape(new_match.a) {
+                        value.set_edge_shape(new_match.a, shape);
+                    }
+                }
+            });
+        });
+    }
+
+    /// Coverage of the edited rule, recomputed only after an edit.
+    fn current_validation(&mut self, value: &TilingStep) -> Option<RuleValidation> {
+        if self.editing_seed {
+            return None;
+        }
+        let fingerprint = value.fingerprint();
+        let tile = self.current_tile;
+        let current =
+            |cached: &CachedValidation| cached.tile == tile && cached.fingerprint == fingerprint;
+        if !self.validation.as_ref().map_or(false, current) {
+            self.validation = Some(CachedValidation {
+                tile,
+                fingerprint,
+                validation: value.validate_rule_geometry(tile),
+            });
+        }
+        return self.validation.as_ref()?.validation.clone();
+    }
+
+    fn current_label_check(&self) -> Option<&LabelCheck> {
+        self.label_check.as_ref().filter(|check| {
+            check.editing_seed == self.editing_seed && check.tile == self.current_tile
+        })
+    }
+
+    /// Expands the edited rule or seed patch and lists the neighbours breaking the edge labels.
+    fn label_check_ui(&mut self, ui: &mut egui::Ui, value: &TilingStep) {
+        ui.horizontal(|ui| {
+            ui.label("Edge labels");
+            ui.add(
+                egui::DragValue::new(&mut self.label_check_levels)
+                    .clamp_range(1..=10)
+                    .suffix(" levels"),
+            );
+            if ui.button("Check").clicked() {
+                let input = if self.editing_seed {
+                    value.seed.clone()
+                } else {
+                    vec![TilePlacement {
+                        tile_id: self.current_tile,
+                        transform: Affine::IDENTITY,
+                    }]
+                };
+                let mut tiles = Vec::new();
+                let levels = self.label_check_levels;
+                let limit = Some(LABEL_CHECK_TILE_LIMIT);
+                if value.try_expand_levels(&input, levels, &mut tiles, limit).is_ok() {
+                    let violations = value.check_edge_labels(&tiles, LABEL_CHECK_TOLERANCE);
+                    self.label_check = Some(LabelCheck {
+                        editing_seed: self.editing_seed,
+                        tile: self.current_tile,
+                        levels,
+                        tiles: tiles.len(),
+                        tile_ids: violations
+                            .iter()
+                            .map(|violation| {
+                                (tiles[violation.a.0].tile_id, tiles[violation.b.0].tile_id)
+                            })
+                            .collect(),
+                        violations,
+                    });
+                }
+            }
+        });
+        let Some(check) = self.current_label_check() else {
+            return;
+        };
+        let color = if check.violations.is_empty() {
+            Color32::DARK_GREEN
+        } else {
+            Color32::RED
+        };
+        ui.colored_label(
+            color,
+            format!(
+                "{} violations in {} tiles after {} levels",
+                check.violations.len(),
+                check.tiles,
+                check.levels
+            ),
+        );
+        for (violation, (a, b)) in check.violations.iter().zip(&check.tile_ids).take(20) {
+            ui.label(format!(
+                "tile {a} edge {} / tile {b} edge {} at ({:.3}, {:.3})",
+                violation.a.1, violation.b.1, violation.location.x, violation.location.y
+            ));
+        }
+    }
+
     fn is_selected(&self, tile: usize) -> bool {
         match &self.selection {
             Selection::Shapes { shapes: shape } if shape.contains(&tile) => true,
@@ -341,6 +758,7 @@ impl WindowState {
             .open(&mut open)
             .show(ctx, |ui| {
                 let selected_tile = self.current_tile;
+                let validation = self.current_validation(value);
                 egui::SidePanel::left("tileedit_left")
                     .resizable(true)
                     .default_width(150.0)
@@ -349,20 +767,97 @@ impl WindowState {
                         ui.vertical_centered(|ui| {
                             ui.heading("Left Panel");
                         });
-                        let rule_selection = egui::ComboBox::from_label("Tile")
-                            .selected_text(format!("{selected_tile}"))
+                        let selected_text = if self.editing_seed {
+                            String::from("Seed patch")
+                        } else {
+                            let selected_name = value
+                                .rules
+                                .get(selected_tile)
+                                .map(|rule| rule.name.as_str())
+                                .unwrap_or_default();
+                            format!("{selected_tile} {selected_name}")
+                        };
+                        let was_editing_seed = self.editing_seed;
+                        egui::ComboBox::from_label("Tile")
+                            .selected_text(selected_text)
                             .show_ui(ui, |ui| {
                                 //ui.style_mut().wrap = Some(false);
                                 //ui.set_min_width(60.0);
                                 for (i, rule) in value.rules.iter().enumerate() {
-                                    ui.selectable_value(&mut self.current_tile, i, format!("{i}"));
+                                    let selected = !self.editing_seed && self.current_tile == i;
+                                    let label = format!("{i} {}", rule.name);
+                                    if ui.selectable_label(selected, label).clicked() {
+                                        self.current_tile = i;
+                                        self.editing_seed = false;
+                                    }
+                                }
+                                if ui.selectable_label(self.editing_seed, "Seed patch").clicked() {
+                                    self.editing_seed = true;
                                 }
                             });
 
-                        if rule_selection.response.changed() {
+                        if self.current_tile != selected_tile || self.editing_seed != was_editing_seed {
+                            self.selection = Selection::None;
+                        }
+
+                        ui.horizontal(|ui| {
+                            egui::ComboBox::from_id_source("tileedit_new_tile")
+                                .selected_text(format!("{}", self.new_tile_id))
+                                .show_ui(ui, |ui| {
+                                    for (i, rule) in value.rules.iter().enumerate() {
+                                        ui.selectable_value(
+                                            &mut self.new_tile_id,
+                                            i,
+                                            format!("{i} {}", rule.name),
+                                        );
+                                    }
+                                });
+                            if ui.button("Add").clicked() && self.new_tile_id < value.rules.len() {
+                                let scale = if self.editing_seed {
+                                    1.0
+                                } else {
+                                    1.0 / value.expansion_factor
+                                };
+                                let new_tile = TilePlacement {
+                                    tile_id: self.new_tile_id,
+                                    transform: Affine::scale(scale),
+                                };
+                                if let Some(edited) = self.edited_placements_mut(value) {
+                                    edited.push(new_tile);
+                                    self.selection = Selection::Shapes {
+                                        shapes: vec![edited.len() - 1],
+                                    };
+                                }
+                            }
+                        });
+                        if ui.button("Remove selected").clicked() {
+                            if let Selection::Shapes { shapes } = &self.selection {
+                                let mut removed = shapes.clone();
+                                removed.sort_unstable();
+                                removed.dedup();
+                                if let Some(edited) = self.edited_placements_mut(value) {
+                                    for i in removed.iter().rev() {
+                                        if *i < edited.len() {
+                                            edited.remove(*i);
+                                        }
+                                    }
+                                }
+                            }
                             self.selection = Selection::None;
                         }
 
+                        if !self.editing_seed {
+                            ui.separator();
+                            layer_ui(ui, value, self.current_tile);
+                            ui.separator();
+                            edges_ui(ui, value, self.current_tile);
+                            self.edge_matches_ui(ui, value);
+                            labels_ui(ui, value, self.current_tile);
+                            ui.separator();
+                            self.decorations_ui(ui, value);
+                            ui.separator();
+                        }
+
                         let shift = ui.input(|x| x.modifiers.shift);
                         ui.add_enabled_ui(!shift, |ui| {
                             ui.checkbox(&mut self.snap, "Snap");
@@ -370,6 +865,33 @@ impl WindowState {
 
                         ui.radio_value(&mut self.tool, Tool::Select, "Select");
                         ui.radio_value(&mut self.tool, Tool::Move, "Move");
+
+                        ui.separator();
+                        ui.text_edit_singleline(&mut self.file_path);
+                        ui.horizontal(|ui| {
+                            if ui.button("Save").clicked() {
+                                self.file_status = match value.save(&self.file_path) {
+                                    Ok(()) => format!("Saved {}", self.file_path),
+                                    Err(err) => err.to_string(),
+                                };
+                            }
+                            if ui.button("Load").clicked() {
+                                self.file_status = match TilingStep::load(&self.file_path) {
+                                    Ok(loaded) => {
+                                        *value = loaded;
+                                        self.current_tile = 0;
+                                        self.editing_seed = false;
+                                        self.selection = Selection::None;
+                                        self.decoration_rule = None;
+                                        format!("Loaded {}", self.file_path)
+                                    }
+                                    Err(err) => err.to_string(),
+                                };
+                            }
+                        });
+                        if !self.file_status.is_empty() {
+                            ui.label(&self.file_status);
+                        }
                         /*egui::ScrollArea::vertical().show(ui, |ui| {
 
                         });*/
@@ -383,7 +905,18 @@ impl WindowState {
                         ui.vertical_centered(|ui| {
                             ui.heading("Right Panel");
                         });
-                        egui::ScrollArea::vertical().show(ui, |ui| {});
+                        egui::ScrollArea::vertical().show(ui, |ui| {
+                            if let Err(err) = value.validate() {
+                                ui.colored_label(Color32::RED, err.to_string());
+                            }
+                            analysis_ui(ui, value);
+                            ui.separator();
+                            if let Some(validation) = &validation {
+                                validation_ui(ui, validation);
+                            }
+                            ui.separator();
+                            self.label_check_ui(ui, value);
+                        });
                     });
 
                 egui::CentralPanel::default().show_inside(ui, |ui| {
@@ -419,18 +952,48 @@ impl WindowState {
                             egui::Stroke::new(1.0, Color32::GRAY),
                         );
 
-                        if !(0..=value.rules.len()).contains(&(self.current_tile)) {
-                            return;
+                        if !self.editing_seed {
+                            if self.current_tile >= value.rules.len() {
+                                return;
+                            }
+                            let rule = &value.rules[self.current_tile];
+                            let points = as_outline(&rule.tile, &Affine::IDENTITY, &to_screen);
+
+                            painter.add(egui::Shape::closed_line(
+                                points,
+                                Stroke::new(4.0, Color32::LIGHT_BLUE),
+                            ));
+                            let stroke = Stroke::new(3.0, DECORATION_COLOR);
+                            for line in decoration_lines(&rule.tile, &Affine::IDENTITY, &to_screen) {
+                                painter.add(egui::Shape::line(line, stroke));
+                            }
+                            draw_labels(&painter, &rule.tile, &Affine::IDENTITY, &to_screen);
+                        }
+
+                        if let Some(validation) = &validation {
+                            for issue in &validation.issues {
+                                painter.circle(
+                                    to_screen * to_pos(issue.location),
+                                    6.0,
+                                    Color32::TRANSPARENT,
+                                    Stroke::new(2.0, Color32::RED),
+                                );
+                            }
                         }
-                        let rule = &value.rules[self.current_tile];
-                        let points = as_points(&rule.tile, &Affine::IDENTITY, &to_screen);
 
-                        painter.add(egui::Shape::closed_line(
-                            points,
-                            Stroke::new(4.0, Color32::LIGHT_BLUE),
-                        ));
+                        if let Some(check) = self.current_label_check() {
+                            for violation in &check.violations {
+                                let p = to_screen * to_pos(violation.location);
+                                let (d1, d2) = (egui::Vec2::new(5.0, 5.0), egui::Vec2::new(-5.0, 5.0));
+                                let stroke = Stroke::new(2.0, Color32::from_rgb(220, 0, 220));
+                                painter.line_segment([p - d1, p + d1], stroke);
+                                painter.line_segment([p - d2, p + d2], stroke);
+                            }
+                        }
 
-                        self.display_shapes(ui, value, &(response, painter));
+                        let canvas = (response, painter);
+                        self.display_shapes(ui, value, &canvas);
+                        self.edge_handles(ui, value, &canvas);
                     });
                 });
             });
@@ -438,6 +1001,177 @@ impl WindowState {
     }
 }
 
+/// Output layer of a rule and the name and pen colour of that layer.
+fn layer_ui(ui: &mut egui::Ui, value: &mut TilingStep, rule: usize) {
+    let Some(layer) = value.rules.get_mut(rule).map(|rule| &mut rule.layer) else {
+        return;
+    };
+    ui.horizontal(|ui| {
+        ui.label("Layer");
+        ui.add(egui::DragValue::new(layer).clamp_range(0..=63));
+    });
+    let layer = *layer;
+    let output = value.output_layer_mut(layer);
+    ui.horizontal(|ui| {
+        ui.color_edit_button_srgb(&mut output.color);
+        ui.text_edit_singleline(&mut output.name);
+    });
+}
+
+/// Shape of every edge of a rule's tile, control points in edge coordinates. Changes carry over
+/// to the matched edges.
+fn edges_ui(ui: &mut egui::Ui, value: &mut TilingStep, rule: usize) {
+    let Some(tile) = value.rules.get(rule).map(|rule| &rule.tile) else {
+        return;
+    };
+    let n = tile.corners.len();
+    let mut changed = None;
+    egui::CollapsingHeader::new("Edges").show(ui, |ui| {
+        for i in 0..n {
+            let mut edge = tile.edge(i);
+            ui.horizontal(|ui| {
+                let kinds = [
+                    ("Straight", EdgeShape::Straight),
+                    ("Arc", EdgeShape::Arc { bulge: 0.2 }),
+                    ("Quadratic", EdgeShape::Quadratic(Point::new(0.5, 0.25))),
+                    (
+                        "Cubic",
+                        EdgeShape::Cubic(Point::new(1.0 / 3.0, 0.25), Point::new(2.0 / 3.0, -0.25)),
+                    ),
+                ];
+                let kind_name = kinds
+                    .iter()
+                    .find(|(_, kind)| std::mem::discriminant(kind) == std::mem::discriminant(&edge))
+                    .map(|(name, _)| *name)
+                    .unwrap_or_default();
+                egui::ComboBox::from_id_source(ui.id().with("edge_kind").with(i))
+                    .width(80.0)
+                    .selected_text(format!("{i}-{} {kind_name}", (i + 1) % n))
+                    .show_ui(ui, |ui| {
+                        for (name, kind) in kinds {
+                            let selected =
+                                std::mem::discriminant(&kind) == std::mem::discriminant(&edge);
+                            if ui.selectable_label(selected, name).clicked() && !selected {
+                                edge = kind;
+                            }
+                        }
+                    });
+                let point_ui = |ui: &mut egui::Ui, p: &mut Point| {
+                    ui.add(egui::DragValue::new(&mut p.x).speed(0.01));
+                    ui.add(egui::DragValue::new(&mut p.y).speed(0.01));
+                };
+                match &mut edge {
+                    EdgeShape::Straight => {}
+                    EdgeShape::Arc { bulge } => {
+                        ui.add(egui::DragValue::new(bulge).speed(0.01));
+                    }
+                    EdgeShape::Quadratic(p) => point_ui(ui, p),
+                    EdgeShape::Cubic(p1, p2) => {
+                        point_ui(ui, p1);
+                        point_ui(ui, p2);
+                    }
+                }
+            });
+            if edge != tile.edge(i) {
+                changed = Some((i, edge));
+            }
+        }
+    });
+    if let Some((edge, shape)) = changed {
+        value.set_edge_shape(EdgeRef { rule, edge }, shape);
+    }
+}
+
+/// Matching rule label of every edge of a rule's tile.
+fn labels_ui(ui: &mut egui::Ui, value: &mut TilingStep, rule: usize) {
+    let Some(tile) = value.rules.get_mut(rule).map(|rule| &mut rule.tile) else {
+        return;
+    };
+    let n = tile.corners.len();
+    egui::CollapsingHeader::new("Edge labels").show(ui, |ui| {
+        for i in 0..n {
+            let label = tile.label_mut(i);
+            ui.horizontal(|ui| {
+                ui.label(format!("{i}-{}", (i + 1) % n));
+                ui.add(egui::DragValue::new(&mut label.kind).clamp_range(0..=99));
+                egui::ComboBox::from_id_source(ui.id().with("edge_marking").with(i))
+                    .width(80.0)
+                    .selected_text(format!("{:?}", label.marking))
+                    .show_ui(ui, |ui| {
+                        for marking in [
+                            EdgeMarking::Plain,
+                            EdgeMarking::Forward,
+                            EdgeMarking::Backward,
+                            EdgeMarking::In,
+                            EdgeMarking::Out,
+                        ] {
+                            ui.selectable_value(&mut label.marking, marking, format!("{marking:?}"));
+                        }
+                    });
+            });
+        }
+    });
+}
+
+fn analysis_ui(ui: &mut egui::Ui, value: &TilingStep) {
+    let Some(analysis) = value.analyze() else {
+        return;
+    };
+    ui.label(format!("Inflation factor: {:.6}", analysis.inflation_factor));
+    ui.label(format!("Area eigenvalue: {:.6}", analysis.eigenvalue));
+    if let Some(warning) = analysis.expansion_factor_warning(value.expansion_factor) {
+        ui.colored_label(Color32::RED, warning);
+    }
+    egui::Grid::new("tile_frequencies").striped(true).show(ui, |ui| {
+        ui.label("Tile");
+        ui.label("Frequency");
+        ui.label("Area");
+        ui.label("Polygon area");
+        ui.end_row();
+        for (i, rule) in value.rules.iter().enumerate() {
+            ui.label(format!("{i} {}", rule.name));
+            ui.label(format!("{:.4}", analysis.frequencies[i]));
+            ui.label(format!("{:.4}", analysis.relative_areas[i]));
+            ui.label(format!("{:.4}", analysis.polygon_areas[i]));
+            ui.end_row();
+        }
+    });
+}
+
+fn validation_ui(ui: &mut egui::Ui, validation: &RuleValidation) {
+    let area_color = |area: f64| {
+        if validation.matches_parent_area(area, VALIDATION_TOLERANCE) {
+            Color32::DARK_GREEN
+        } else {
+            Color32::RED
+        }
+    };
+    ui.label(format!("Tile area: {:.5}", validation.parent_area));
+    ui.colored_label(
+        area_color(validation.placed_child_area),
+        format!("Placed children: {:.5}", validation.placed_child_area),
+    );
+    ui.colored_label(
+        area_color(validation.scaled_child_area),
+        format!("Children / factor²: {:.5}", validation.scaled_child_area),
+    );
+    ui.label(format!("Uncovered: {:.5}", validation.uncovered_area));
+    ui.label(format!("Overlap: {:.5}", validation.overlap_area));
+    ui.label(format!("Outside: {:.5}", validation.outside_area));
+    if validation.is_valid(VALIDATION_TOLERANCE) {
+        ui.colored_label(Color32::DARK_GREEN, "Children cover the tile");
+    }
+    for issue in &validation.issues {
+        ui.colored_label(
+            Color32::RED,
+            format!(
+                "{:?} {:.5} at ({:.3}, {:.3})",
+                issue.kind, issue.area, issue.location.x, issue.location.y
+            ),
+        );
+    }
+}
+
 impl Widget<TilingStep> for TilingEditorWidget {
     fn ui(&self, ui: &mut egui::Ui, label: &str, value: &mut TilingStep) -> bool {
         let window_id = Id::new("My window");
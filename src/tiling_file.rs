@@ -0,0 +1,459 @@
+use std::fmt;
+use std::path::Path;
+
+use kurbo::{Affine, BezPath, Point, SvgParseError};
+use serde::{Deserialize, Serialize};
+
+use crate::edge_matching::{EdgeMatch, EdgeRef};
+use crate::layers::OutputLayer;
+use crate::tiling::*;
+
+pub const TILING_FILE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct TilingFile {
+    version: u32,
+    expansion_factor: f64,
+    rules: Vec<RuleFile>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    seed: Vec<PlacementFile>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    layers: Vec<LayerFile>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    edge_matches: Vec<EdgeMatchFile>,
+}
+
+/// Edges as `[rule, edge]` pairs.
+#[derive(Serialize, Deserialize)]
+struct EdgeMatchFile {
+    a: [usize; 2],
+    b: [usize; 2],
+    #[serde(default)]
+    reversed: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LayerFile {
+    name: String,
+    color: [u8; 3],
+}
+
+#[derive(Serialize, Deserialize)]
+struct RuleFile {
+    #[serde(default)]
+    name: String,
+    corners: Vec<[f64; 2]>,
+    result: Vec<PlacementFile>,
+    /// Output layer, defaults to the rule index.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    layer: Option<usize>,
+    /// Shape of every edge, all straight when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    edges: Vec<EdgeFile>,
+    /// Matching rule label of every edge.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    labels: Vec<LabelFile>,
+    /// SVG path data, one string per decoration.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    decorations: Vec<String>,
+}
+
+/// `EdgeShape` with control points in edge coordinates.
+#[derive(Serialize, Deserialize)]
+enum EdgeFile {
+    Straight,
+    Quadratic([f64; 2]),
+    Cubic([f64; 2], [f64; 2]),
+    Arc { bulge: f64 },
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+enum MarkingFile {
+    #[default]
+    Plain,
+    Forward,
+    Backward,
+    In,
+    Out,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LabelFile {
+    kind: u32,
+    #[serde(default)]
+    marking: MarkingFile,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlacementFile {
+    tile_id: usize,
+    /// Affine coefficients `[a, b, c, d, e, f]` as used by `kurbo::Affine::new`.
+    transform: [f64; 6],
+}
+
+#[derive(Debug)]
+pub enum TilingFileError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+    UnsupportedVersion(u32),
+    InvalidDecoration { rule: usize, error: SvgParseError },
+    Invalid(TilingError),
+}
+
+impl fmt::Display for TilingFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TilingFileError::Io(err) => write!(f, "failed to access tiling file: {err}"),
+            TilingFileError::Parse(err) => write!(f, "malformed tiling file: {err}"),
+            TilingFileError::Serialize(err) => write!(f, "failed to serialize tiling: {err}"),
+            TilingFileError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported tiling file version {version} (expected {TILING_FILE_VERSION})"
+            ),
+            TilingFileError::InvalidDecoration { rule, error } => {
+                write!(f, "invalid decoration path in rule {rule}: {error}")
+            }
+            TilingFileError::Invalid(err) => write!(f, "invalid tiling: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for TilingFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TilingFileError::Io(err) => Some(err),
+            TilingFileError::Parse(err) => Some(err),
+            TilingFileError::Serialize(err) => Some(err),
+            TilingFileError::InvalidDecoration { error, .. } => Some(error),
+            TilingFileError::Invalid(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<TilingError> for TilingFileError {
+    fn from(err: TilingError) -> Self {
+        TilingFileError::Invalid(err)
+    }
+}
+
+impl From<std::io::Error> for TilingFileError {
+    fn from(err: std::io::Error) -> Self {
+        TilingFileError::Io(err)
+    }
+}
+
+impl From<ron::error::SpannedError> for TilingFileError {
+    fn from(err: ron::error::SpannedError) -> Self {
+        TilingFileError::Parse(err)
+    }
+}
+
+impl From<ron::Error> for TilingFileError {
+    fn from(err: ron::Error) -> Self {
+        TilingFileError::Serialize(err)
+    }
+}
+
+impl EdgeFile {
+    fn from_edge(edge: &EdgeShape) -> EdgeFile {
+        match *edge {
+            EdgeShape::Straight => EdgeFile::Straight,
+            EdgeShape::Quadratic(p) => EdgeFile::Quadratic([p.x, p.y]),
+            EdgeShape::Cubic(p1, p2) => EdgeFile::Cubic([p1.x, p1.y], [p2.x, p2.y]),
+            EdgeShape::Arc { bulge } => EdgeFile::Arc { bulge },
+        }
+    }
+
+    fn to_edge(&self) -> EdgeShape {
+        let point = |p: &[f64; 2]| Point::new(p[0], p[1]);
+        match self {
+            EdgeFile::Straight => EdgeShape::Straight,
+            EdgeFile::Quadratic(p) => EdgeShape::Quadratic(point(p)),
+            EdgeFile::Cubic(p1, p2) => EdgeShape::Cubic(point(p1), point(p2)),
+            EdgeFile::Arc { bulge } => EdgeShape::Arc { bulge: *bulge },
+        }
+    }
+}
+
+impl LabelFile {
+    fn from_label(label: &EdgeLabel) -> LabelFile {
+        let marking = match label.marking {
+            EdgeMarking::Plain => MarkingFile::Plain,
+            EdgeMarking::Forward => MarkingFile::Forward,
+            EdgeMarking::Backward => MarkingFile::Backward,
+            EdgeMarking::In => MarkingFile::In,
+            EdgeMarking::Out => MarkingFile::Out,
+        };
+        LabelFile {
+            kind: label.kind,
+            marking,
+        }
+    }
+
+    fn to_label(&self) -> EdgeLabel {
+        let marking = match self.marking {
+            MarkingFile::Plain => EdgeMarking::Plain,
+            MarkingFile::Forward => EdgeMarking::Forward,
+            MarkingFile::Backward => EdgeMarking::Backward,
+            MarkingFile::In => EdgeMarking::In,
+            MarkingFile::Out => EdgeMarking::Out,
+        };
+        EdgeLabel {
+            kind: self.kind,
+            marking,
+        }
+    }
+}
+
+impl PlacementFile {
+    fn from_placement(placement: &TilePlacement) -> PlacementFile {
+        PlacementFile {
+            tile_id: placement.tile_id,
+            transform: placement.transform.as_coeffs(),
+        }
+    }
+
+    fn to_placement(&self) -> TilePlacement {
+        TilePlacement {
+            tile_id: self.tile_id,
+            transform: Affine::new(self.transform),
+        }
+    }
+}
+
+impl TilingFile {
+    fn from_step(step: &TilingStep) -> TilingFile {
+        TilingFile {
+            version: TILING_FILE_VERSION,
+            expansion_factor: step.expansion_factor,
+            rules: step
+                .rules
+                .iter()
+                .enumerate()
+                .map(|(i, rule)| RuleFile {
+                    name: rule.name.clone(),
+                    corners: rule.tile.corners.iter().map(|p| [p.x, p.y]).collect(),
+                    result: rule
+                        .result
+                        .iter()
+                        .map(PlacementFile::from_placement)
+                        .collect(),
+                    layer: Some(rule.layer).filter(|layer| *layer != i),
+                    edges: if rule.tile.is_polygon() {
+                        Vec::new()
+                    } else {
+                        rule.tile.edges.iter().map(EdgeFile::from_edge).collect()
+                    },
+                    labels: if rule.tile.labels.iter().all(|label| label.kind == 0) {
+                        Vec::new()
+                    } else {
+                        rule.tile.labels.iter().map(LabelFile::from_label).collect()
+                    },
+                    decorations: rule.tile.decorations.iter().map(BezPath::to_svg).collect(),
+                })
+                .collect(),
+            seed: step.seed.iter().map(PlacementFile::from_placement).collect(),
+            layers: step
+                .layers
+                .iter()
+                .map(|layer| LayerFile {
+                    name: layer.name.clone(),
+                    color: layer.color,
+                })
+                .collect(),
+            edge_matches: step
+                .edge_matches
+                .iter()
+                .map(|matched| EdgeMatchFile {
+                    a: [matched.a.rule, matched.a.edge],
+                    b: [matched.b.rule, matched.b.edge],
+                    reversed: matched.reversed,
+                })
+                .collect(),
+        }
+    }
+
+    fn into_step(self) -> Result<TilingStep, TilingFileError> {
+        if self.version != TILING_FILE_VERSION {
+            return Err(TilingFileError::UnsupportedVersion(self.version));
+        }
+        let mut step = TilingStep::new();
+        step.expansion_factor = self.expansion_factor;
+        for (i, rule) in self.rules.into_iter().enumerate() {
+            let decorations = rule
+                .decorations
+                .iter()
+                .map(|data| BezPath::from_svg(data))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|error| TilingFileError::InvalidDecoration { rule: i, error })?;
+            step.rules.push(TilingRule {
+                name: rule.name,
+                tile: Tile {
+                    corners: rule.corners.iter().map(|p| Point::new(p[0], p[1])).collect(),
+                    edges: rule.edges.iter().map(EdgeFile::to_edge).collect(),
+                    labels: rule.labels.iter().map(LabelFile::to_label).collect(),
+                    decorations,
+                },
+                result: rule.result.iter().map(PlacementFile::to_placement).collect(),
+                layer: rule.layer.unwrap_or(i),
+            });
+        }
+        step.seed = self.seed.iter().map(PlacementFile::to_placement).collect();
+        step.layers = self
+            .layers
+            .into_iter()
+            .map(|layer| OutputLayer {
+                name: layer.name,
+                color: layer.color,
+            })
+            .collect();
+        let edge = |[rule, edge]: [usize; 2]| EdgeRef { rule, edge };
+        step.edge_matches = self
+            .edge_matches
+            .iter()
+            .map(|matched| EdgeMatch {
+                a: edge(matched.a),
+                b: edge(matched.b),
+                reversed: matched.reversed,
+            })
+            .collect();
+        step.validate()?;
+        return Ok(step);
+    }
+}
+
+impl TilingStep {
+    pub fn from_ron_str(text: &str) -> Result<TilingStep, TilingFileError> {
+        let file: TilingFile = ron::from_str(text)?;
+        return file.into_step();
+    }
+
+    pub fn to_ron_string(&self) -> Result<String, TilingFileError> {
+        let config = ron::ser::PrettyConfig::new();
+        return Ok(ron::ser::to_string_pretty(
+            &TilingFile::from_step(self),
+            config,
+        )?);
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<TilingStep, TilingFileError> {
+        let text = std::fs::read_to_string(path)?;
+        return TilingStep::from_ron_str(&text);
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), TilingFileError> {
+        std::fs::write(path, self.to_ron_string()?)?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets;
+
+    fn assert_same_placements(a: &[TilePlacement], b: &[TilePlacement]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b) {
+            assert_eq!(a.tile_id, b.tile_id);
+            assert_eq!(a.transform.as_coeffs(), b.transform.as_coeffs());
+        }
+    }
+
+    fn assert_same_step(a: &TilingStep, b: &TilingStep) {
+        assert_eq!(a.expansion_factor, b.expansion_factor);
+        assert_eq!(a.rules.len(), b.rules.len());
+        for (a, b) in a.rules.iter().zip(&b.rules) {
+            assert_eq!(a.name, b.name);
+            assert_eq!(a.layer, b.layer);
+            assert_eq!(a.tile.corners, b.tile.corners);
+            assert_eq!(a.tile.edges, b.tile.edges);
+            assert_eq!(a.tile.labels, b.tile.labels);
+            let svg = |tile: &Tile| -> Vec<String> {
+                tile.decorations.iter().map(BezPath::to_svg).collect()
+            };
+            assert_eq!(svg(&a.tile), svg(&b.tile));
+            assert_same_placements(&a.result, &b.result);
+        }
+        assert_same_placements(&a.seed, &b.seed);
+        assert_eq!(a.layers, b.layers);
+        assert_eq!(a.edge_matches, b.edge_matches);
+    }
+
+    const MINIMAL: &str = "(
+        version: 1,
+        expansion_factor: 2.0,
+        rules: [
+            (
+                corners: [(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, 0.0)],
+                result: [(tile_id: 0, transform: (0.5, 0.0, 0.0, 0.5, 0.0, 0.0))],
+            ),
+        ],
+    )";
+
+    #[test]
+    fn presets_round_trip() {
+        for name in presets::preset_names() {
+            let step = presets::load_preset(name).unwrap();
+            let text = step.to_ron_string().unwrap();
+            let loaded = TilingStep::from_ron_str(&text).unwrap();
+            assert_same_step(&step, &loaded);
+        }
+    }
+
+    #[test]
+    fn minimal_file_loads() {
+        let step = TilingStep::from_ron_str(MINIMAL).unwrap();
+        assert_eq!(step.rules.len(), 1);
+        assert_eq!(step.rules[0].layer, 0);
+    }
+
+    #[test]
+    fn rejects_wrong_version() {
+        let text = MINIMAL.replace("version: 1", "version: 99");
+        assert!(matches!(
+            TilingStep::from_ron_str(&text),
+            Err(TilingFileError::UnsupportedVersion(99))
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_ron() {
+        let text = &MINIMAL[..MINIMAL.len() / 2];
+        assert!(matches!(
+            TilingStep::from_ron_str(text),
+            Err(TilingFileError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_out_of_range_tile_id() {
+        let text = MINIMAL.replace("tile_id: 0", "tile_id: 3");
+        assert!(matches!(
+            TilingStep::from_ron_str(&text),
+            Err(TilingFileError::Invalid(TilingError::InvalidTileId {
+                rule: 0,
+                child: 0,
+                tile_id: 3
+            }))
+        ));
+    }
+
+    #[test]
+    fn rejects_dangling_edge_match() {
+        let text = MINIMAL.replace(
+            "expansion_factor: 2.0,",
+            "expansion_factor: 2.0, edge_matches: [(a: (0, 1), b: (0, 7), reversed: true)],",
+        );
+        assert!(matches!(
+            TilingStep::from_ron_str(&text),
+            Err(TilingFileError::Invalid(TilingError::InvalidEdgeMatch {
+                index: 0,
+                rule: 0,
+                edge: 7
+            }))
+        ));
+    }
+}
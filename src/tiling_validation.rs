@@ -0,0 +1,398 @@
+use kurbo::{Affine, Point, Rect};
+
+use crate::tiling::*;
+
+/// Coverage regions smaller than this, relative to the tile area, are rounding noise along edges
+/// that children share with each other or with the tile.
+const MIN_ISSUE_AREA: f64 = 1e-6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IssueKind {
+    /// Part of the parent not covered by any child.
+    Gap,
+    /// Area covered by more than one child.
+    Overlap,
+    /// Child area outside the parent tile.
+    Outside,
+}
+
+#[derive(Clone, Debug)]
+pub struct CoverageIssue {
+    pub kind: IssueKind,
+    /// Point inside the affected region in the rule's coordinates, the centre of its largest
+    /// piece.
+    pub location: Point,
+    pub area: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct RuleValidation {
+    pub rule: usize,
+    pub parent_area: f64,
+    /// Sum of the child areas after applying their placement transforms.
+    pub placed_child_area: f64,
+    /// Sum of the child prototile areas divided by the expansion factor squared.
+    pub scaled_child_area: f64,
+    pub uncovered_area: f64,
+    pub overlap_area: f64,
+    pub outside_area: f64,
+    pub issues: Vec<CoverageIssue>,
+}
+
+impl RuleValidation {
+    /// Whether `area` is within `relative_tolerance` of the tile area.
+    pub fn matches_parent_area(&self, area: f64, relative_tolerance: f64) -> bool {
+        (area - self.parent_area).abs() <= self.parent_area * relative_tolerance
+    }
+
+    pub fn is_valid(&self, relative_tolerance: f64) -> bool {
+        self.matches_parent_area(self.placed_child_area, relative_tolerance)
+            && self.matches_parent_area(self.scaled_child_area, relative_tolerance)
+            && self.issues.is_empty()
+    }
+}
+
+/// Even-odd point in polygon test.
+pub fn polygon_contains(polygon: &[Point], p: Point) -> bool {
+    let mut inside = false;
+    let n = polygon.len();
+    for i in 0..n {
+        let a = polygon[i];
+        let b = polygon[(i + 1) % n];
+        if (a.y > p.y) != (b.y > p.y) {
+            let x = a.x + (p.y - a.y) * (b.x - a.x) / (b.y - a.y);
+            if p.x < x {
+                inside = !inside;
+            }
+        }
+    }
+    return inside;
+}
+
+fn transformed_outline(tile: &Tile, transform: Affine) -> Vec<Point> {
+    tile.outline_points()
+        .into_iter()
+        .map(|p| transform * p)
+        .collect()
+}
+
+/// Polygon edge running from left to right, vertical edges never bound a slab.
+struct SlabEdge {
+    a: Point,
+    b: Point,
+    polygon: usize,
+}
+
+impl SlabEdge {
+    fn y_at(&self, x: f64) -> f64 {
+        self.a.y + (x - self.a.x) * (self.b.y - self.a.y) / (self.b.x - self.a.x)
+    }
+}
+
+/// Part of a coverage issue between two edges inside one slab.
+struct Trapezoid {
+    kind: IssueKind,
+    corners: [Point; 4],
+    area: f64,
+}
+
+/// Area weighted centre of a simple polygon.
+fn polygon_centroid(polygon: &[Point]) -> Point {
+    let n = polygon.len();
+    let mut twice_area = 0.0;
+    let mut sum = Point::ZERO.to_vec2();
+    for i in 0..n {
+        let (a, b) = (polygon[i], polygon[(i + 1) % n]);
+        let cross = a.to_vec2().cross(b.to_vec2());
+        twice_area += cross;
+        sum += (a.to_vec2() + b.to_vec2()) * cross;
+    }
+    if twice_area.abs() <= f64::EPSILON {
+        let mean = polygon.iter().fold(Point::ZERO.to_vec2(), |sum, p| sum + p.to_vec2());
+        return (mean / n as f64).to_point();
+    }
+    return (sum / (3.0 * twice_area)).to_point();
+}
+
+fn find_root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    return i;
+}
+
+/// Exact coverage of `polygons[0]` by the other polygons. The plane is cut into vertical slabs
+/// at every vertex and edge crossing, inside a slab the edges don't cross, so the regions
+/// between consecutive edges are trapezoids covered by a fixed set of polygons. Trapezoids with
+/// a problem are joined into regions across shared sides and reported with the centre of their
+/// largest trapezoid, which lies inside the region.
+fn coverage_issues(polygons: &[Vec<Point>], min_area: f64) -> Vec<CoverageIssue> {
+    let mut bounds = Rect::from_points(polygons[0][0], polygons[0][0]);
+    for p in polygons.iter().flatten() {
+        bounds = bounds.union_pt(*p);
+    }
+    let eps = 1e-12 * f64::max(bounds.width(), bounds.height()).max(1.0);
+
+    let mut edges: Vec<SlabEdge> = Vec::new();
+    for (polygon, points) in polygons.iter().enumerate() {
+        let n = points.len();
+        for i in 0..n {
+            let (a, b) = (points[i], points[(i + 1) % n]);
+            if (a.x - b.x).abs() <= eps {
+                continue;
+            }
+            let (a, b) = if a.x < b.x { (a, b) } else { (b, a) };
+            edges.push(SlabEdge { a, b, polygon });
+        }
+    }
+    edges.sort_by(|e, f| e.a.x.total_cmp(&f.a.x));
+
+    // Slab boundaries: every vertex and every proper crossing of two edges.
+    let mut xs: Vec<f64> = polygons.iter().flatten().map(|p| p.x).collect();
+    for (i, e) in edges.iter().enumerate() {
+        for f in edges[i + 1..].iter().take_while(|f| f.a.x < e.b.x) {
+            let (r, s) = (e.b - e.a, f.b - f.a);
+            let denominator = r.cross(s);
+            if denominator.abs() <= f64::EPSILON * r.hypot() * s.hypot() {
+                continue;
+            }
+            let t = (f.a - e.a).cross(s) / denominator;
+            let u = (f.a - e.a).cross(r) / denominator;
+            if t > 0.0 && t < 1.0 && u > 0.0 && u < 1.0 {
+                xs.push(e.a.x + t * r.x);
+            }
+        }
+    }
+    xs.sort_by(f64::total_cmp);
+    xs.dedup_by(|x, previous| *x - *previous <= eps);
+
+    let mut trapezoids: Vec<Trapezoid> = Vec::new();
+    let mut parents: Vec<usize> = Vec::new();
+    // Trapezoids of the previous slab with their extent along its right side.
+    let mut previous: Vec<(usize, f64, f64)> = Vec::new();
+    let mut next_edge = 0;
+    let mut active: Vec<&SlabEdge> = Vec::new();
+    for slab in xs.windows(2) {
+        let (x0, x1) = (slab[0], slab[1]);
+        while next_edge < edges.len() && edges[next_edge].a.x <= x0 + eps {
+            active.push(&edges[next_edge]);
+            next_edge += 1;
+        }
+        active.retain(|edge| edge.b.x >= x1 - eps);
+        let middle = 0.5 * (x0 + x1);
+        active.sort_by(|e, f| e.y_at(middle).total_cmp(&f.y_at(middle)));
+
+        let mut inside = vec![false; polygons.len()];
+        let mut covering = 0usize;
+        let mut current: Vec<(usize, f64, f64)> = Vec::new();
+        // Last trapezoid with an area, zero height ones between coinciding edges are skipped.
+        let mut below: Option<usize> = None;
+        for pair in active.windows(2) {
+            let (lower, upper) = (pair[0], pair[1]);
+            inside[lower.polygon] = !inside[lower.polygon];
+            if lower.polygon > 0 {
+                covering = if inside[lower.polygon] {
+                    covering + 1
+                } else {
+                    covering - 1
+                };
+            }
+            let bottom = (lower.y_at(x0), lower.y_at(x1));
+            let top = (upper.y_at(x0), upper.y_at(x1));
+            let area = 0.5 * (x1 - x0) * ((top.0 - bottom.0) + (top.1 - bottom.1));
+            if area <= eps * eps {
+                continue;
+            }
+            let kind = match (inside[0], covering) {
+                (true, 0) => Some(IssueKind::Gap),
+                (_, c) if c > 1 => Some(IssueKind::Overlap),
+                (false, c) if c > 0 => Some(IssueKind::Outside),
+                _ => None,
+            };
+            let Some(kind) = kind else {
+                below = None;
+                continue;
+            };
+            let index = trapezoids.len();
+            trapezoids.push(Trapezoid {
+                kind,
+                corners: [
+                    Point::new(x0, bottom.0),
+                    Point::new(x1, bottom.1),
+                    Point::new(x1, top.1),
+                    Point::new(x0, top.0),
+                ],
+                area,
+            });
+            parents.push(index);
+            if let Some(other) = below.filter(|&other| trapezoids[other].kind == kind) {
+                let root = find_root(&mut parents, other);
+                parents[root] = index;
+            }
+            below = Some(index);
+            // Join with the trapezoids of the previous slab that share a piece of this side.
+            for &(other, low, high) in &previous {
+                let overlap = high.min(top.0) - low.max(bottom.0);
+                if overlap > eps && trapezoids[other].kind == kind {
+                    let (a, b) = (find_root(&mut parents, other), find_root(&mut parents, index));
+                    parents[a] = b;
+                }
+            }
+            current.push((index, bottom.1, top.1));
+        }
+        previous = current;
+    }
+
+    let mut regions: Vec<(f64, usize)> = vec![(0.0, usize::MAX); trapezoids.len()];
+    for i in 0..trapezoids.len() {
+        let root = find_root(&mut parents, i);
+        let (area, largest) = &mut regions[root];
+        *area += trapezoids[i].area;
+        if *largest == usize::MAX || trapezoids[i].area > trapezoids[*largest].area {
+            *largest = i;
+        }
+    }
+    return regions
+        .into_iter()
+        .filter(|(area, largest)| *largest != usize::MAX && *area > min_area)
+        .map(|(area, largest)| CoverageIssue {
+            kind: trapezoids[largest].kind,
+            location: polygon_centroid(&trapezoids[largest].corners),
+            area,
+        })
+        .collect();
+}
+
+impl TilingStep {
+    /// Checks how well the children of a rule cover its tile. Areas are exact for polygons,
+    /// curved edges are flattened first.
+    pub fn validate_rule_geometry(&self, rule_id: usize) -> Option<RuleValidation> {
+        let rule = self.rules.get(rule_id)?;
+        let parent = rule.tile.outline_points();
+        if parent.is_empty() {
+            return None;
+        }
+        let mut polygons = vec![parent];
+        for placement in &rule.result {
+            if let Some(child) = self.rules.get(placement.tile_id) {
+                polygons.push(transformed_outline(&child.tile, placement.transform));
+            }
+        }
+
+        let parent_area = rule.tile.area();
+        let placed_child_area = rule
+            .result
+            .iter()
+            .filter_map(|placement| {
+                let tile = &self.rules.get(placement.tile_id)?.tile;
+                Some(tile.area() * placement.transform.determinant().abs())
+            })
+            .sum();
+        let scaled_child_area = rule
+            .result
+            .iter()
+            .filter_map(|placement| Some(self.rules.get(placement.tile_id)?.tile.area()))
+            .sum::<f64>()
+            / (self.expansion_factor * self.expansion_factor);
+
+        let issues = coverage_issues(&polygons, parent_area * MIN_ISSUE_AREA);
+        let total = |kind: IssueKind| -> f64 {
+            issues
+                .iter()
+                .filter(|issue| issue.kind == kind)
+                .fold(0.0, |sum, issue| sum + issue.area)
+        };
+
+        Some(RuleValidation {
+            rule: rule_id,
+            parent_area,
+            placed_child_area,
+            scaled_child_area,
+            uncovered_area: total(IssueKind::Gap),
+            overlap_area: total(IssueKind::Overlap),
+            outside_area: total(IssueKind::Outside),
+            issues,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn validate_geometry(&self) -> Vec<RuleValidation> {
+        (0..self.rules.len())
+            .filter_map(|rule| self.validate_rule_geometry(rule))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets;
+
+    const TOLERANCE: f64 = 1e-6;
+
+    fn square_grid() -> TilingStep {
+        presets::load_preset("square_grid").unwrap()
+    }
+
+    #[test]
+    fn presets_are_valid() {
+        for name in ["socolar_5", "square_grid"] {
+            let tiling = presets::load_preset(name).unwrap();
+            for validation in tiling.validate_geometry() {
+                assert!(
+                    validation.is_valid(TOLERANCE),
+                    "{name} rule {}: {validation:?}",
+                    validation.rule
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn finds_gap() {
+        let mut tiling = square_grid();
+        tiling.rules[0].result.pop();
+        let validation = tiling.validate_rule_geometry(0).unwrap();
+        assert!(!validation.is_valid(TOLERANCE));
+        assert_eq!(validation.issues.len(), 1);
+        let gap = &validation.issues[0];
+        assert_eq!(gap.kind, IssueKind::Gap);
+        assert!((gap.area - 0.25).abs() < TOLERANCE);
+        assert!(gap.location.distance(Point::new(0.75, 0.75)) < TOLERANCE);
+    }
+
+    #[test]
+    fn finds_overlap() {
+        let mut tiling = square_grid();
+        tiling.rules[0].result[1].transform = Affine::new([0.5, 0.0, 0.0, 0.5, 0.25, 0.0]);
+        let validation = tiling.validate_rule_geometry(0).unwrap();
+        assert!(!validation.is_valid(TOLERANCE));
+        assert!((validation.overlap_area - 0.125).abs() < TOLERANCE);
+        assert!((validation.uncovered_area - 0.125).abs() < TOLERANCE);
+        assert_eq!(validation.outside_area, 0.0);
+        let overlap = validation
+            .issues
+            .iter()
+            .find(|issue| issue.kind == IssueKind::Overlap)
+            .unwrap();
+        assert!(overlap.location.distance(Point::new(0.375, 0.25)) < TOLERANCE);
+    }
+
+    #[test]
+    fn finds_outside() {
+        let mut tiling = square_grid();
+        tiling.rules[0].result[1].transform = Affine::new([0.5, 0.0, 0.0, 0.5, 0.75, 0.0]);
+        let validation = tiling.validate_rule_geometry(0).unwrap();
+        assert!(!validation.is_valid(TOLERANCE));
+        assert!((validation.outside_area - 0.125).abs() < TOLERANCE);
+        assert!((validation.uncovered_area - 0.125).abs() < TOLERANCE);
+        assert_eq!(validation.overlap_area, 0.0);
+        let outside = validation
+            .issues
+            .iter()
+            .find(|issue| issue.kind == IssueKind::Outside)
+            .unwrap();
+        assert!(outside.location.distance(Point::new(1.125, 0.25)) < TOLERANCE);
+    }
+}